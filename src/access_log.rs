@@ -0,0 +1,371 @@
+use anyhow::Result;
+use hyper::Request;
+use serde_json::json;
+use std::fmt::Write as _;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::logfile::{RotatingFile, LOG_MAX_BYTES};
+use crate::types::CapturedExchange;
+use crate::utils::{format_clf_time, format_rfc3339};
+
+/// Access log inside the state dir, next to `proxy.log`.
+pub const ACCESS_LOG_FILE: &str = "access.log";
+/// Lines waiting for the writer thread; more are dropped while the disk is
+/// this far behind, rather than holding up requests.
+const ACCESS_LOG_BACKLOG: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AccessLogFormat {
+    /// NCSA Common Log Format
+    Common,
+    /// Common Log Format plus referer and user agent
+    Combined,
+    /// One JSON object per line
+    Json,
+    /// Don't write an access log
+    Off,
+}
+
+/// One line of the access log.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AccessEntry {
+    /// Request start in milliseconds since the Unix epoch.
+    pub time_ms: u64,
+    pub client_ip: String,
+    pub hostname: String,
+    pub backend_port: u16,
+    pub method: String,
+    pub uri: String,
+    pub protocol: String,
+    pub status: u16,
+    /// Response body bytes sent to the client.
+    pub bytes: u64,
+    /// From request start until the response body was sent.
+    pub duration_ms: f64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    /// The connection was upgraded to a WebSocket tunnel.
+    pub websocket: bool,
+    pub error: Option<String>,
+}
+
+impl AccessEntry {
+    /// Entry for a WebSocket handshake; status and duration are filled in once
+    /// the backend has answered.
+    pub fn websocket<B>(
+        req: &Request<B>,
+        hostname: &str,
+        backend_port: u16,
+        client_ip: IpAddr,
+    ) -> Self {
+        Self {
+            websocket: true,
+            ..Self::request(req, hostname, backend_port, client_ip)
+        }
+    }
+
+    /// Entry for `req` as it arrived; the response fields are filled in once
+    /// it has been answered.
+    pub fn request<B>(
+        req: &Request<B>,
+        hostname: &str,
+        backend_port: u16,
+        client_ip: IpAddr,
+    ) -> Self {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map(|v| String::from_utf8_lossy(v.as_bytes()).into_owned())
+        };
+        Self {
+            time_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            client_ip: client_ip.to_string(),
+            hostname: hostname.to_string(),
+            backend_port,
+            method: req.method().to_string(),
+            uri: req
+                .uri()
+                .path_and_query()
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| "/".to_string()),
+            protocol: format!("{:?}", req.version()),
+            referer: header("referer"),
+            user_agent: header("user-agent"),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_exchange(ex: &CapturedExchange) -> Self {
+        let header = |name: &str| {
+            ex.request_headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, v)| v.clone())
+        };
+        Self {
+            time_ms: ex.started_at_ms,
+            client_ip: ex.client_ip.clone(),
+            hostname: ex.hostname.clone(),
+            backend_port: ex.backend_port,
+            method: ex.method.clone(),
+            uri: ex.uri.clone(),
+            protocol: ex.http_version.clone(),
+            // The client saw the connection fail, which the stats count as a 502.
+            status: ex.status.unwrap_or(502),
+            bytes: ex.response_body.size,
+            duration_ms: ex.duration_ms + ex.timings.receive_ms,
+            referer: header("referer"),
+            user_agent: header("user-agent"),
+            websocket: false,
+            error: ex.error.clone(),
+        }
+    }
+}
+
+/// Render `entry` as one newline-terminated line. The CLF formats carry the
+/// portless-specific fields as `key=value` pairs after the standard ones.
+pub fn format_entry(entry: &AccessEntry, format: AccessLogFormat) -> String {
+    if format == AccessLogFormat::Json {
+        let mut value = json!({
+            "time": format_rfc3339(entry.time_ms),
+            "client": entry.client_ip,
+            "host": entry.hostname,
+            "backend_port": entry.backend_port,
+            "method": entry.method,
+            "uri": entry.uri,
+            "protocol": entry.protocol,
+            "status": entry.status,
+            "bytes": entry.bytes,
+            "duration_ms": (entry.duration_ms * 1000.0).round() / 1000.0,
+            "referer": entry.referer,
+            "user_agent": entry.user_agent,
+        });
+        if entry.websocket {
+            value["upgrade"] = json!("websocket");
+        }
+        if let Some(error) = &entry.error {
+            value["error"] = json!(error);
+        }
+        return format!("{}\n", value);
+    }
+
+    let mut line = format!(
+        "{} - - [{}] {} {} {}",
+        or_dash(&entry.client_ip),
+        format_clf_time(entry.time_ms),
+        quote(&format!("{} {} {}", entry.method, entry.uri, entry.protocol)),
+        entry.status,
+        if entry.bytes == 0 {
+            "-".to_string()
+        } else {
+            entry.bytes.to_string()
+        }
+    );
+    if format == AccessLogFormat::Combined {
+        let _ = write!(
+            line,
+            " {} {}",
+            quote(entry.referer.as_deref().unwrap_or("-")),
+            quote(entry.user_agent.as_deref().unwrap_or("-"))
+        );
+    }
+    let _ = write!(
+        line,
+        " host={} backend={} duration_ms={:.3}",
+        entry.hostname, entry.backend_port, entry.duration_ms
+    );
+    if entry.websocket {
+        line.push_str(" upgrade=websocket");
+    }
+    line.push('\n');
+    line
+}
+
+fn or_dash(value: &str) -> &str {
+    if value.is_empty() { "-" } else { value }
+}
+
+/// Double-quote `value`, escaping quotes, backslashes and control characters.
+fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\x{:02x}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Writes access log lines to `<state dir>/access.log`, rotating it by size.
+/// The file is written on a thread of its own, so a slow disk or a rotation
+/// never stalls the proxy's workers.
+pub struct AccessLog {
+    format: AccessLogFormat,
+    lines: Option<SyncSender<String>>,
+    writer: Option<JoinHandle<()>>,
+}
+
+impl AccessLog {
+    pub fn open(state_dir: &Path, format: AccessLogFormat) -> Result<Self> {
+        let mut file = RotatingFile::open(state_dir.join(ACCESS_LOG_FILE), LOG_MAX_BYTES)?;
+        let (lines, queued) = mpsc::sync_channel::<String>(ACCESS_LOG_BACKLOG);
+        let writer = thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || {
+                for line in queued {
+                    if let Err(e) = file.write_line(&line) {
+                        eprintln!("portless proxy: cannot write access log: {}", e);
+                    }
+                }
+            })?;
+        Ok(Self {
+            format,
+            lines: Some(lines),
+            writer: Some(writer),
+        })
+    }
+
+    pub fn log(&self, entry: &AccessEntry) {
+        if let Some(lines) = &self.lines {
+            let _ = lines.try_send(format_entry(entry, self.format));
+        }
+    }
+}
+
+impl Drop for AccessLog {
+    /// Write out the queued lines before going away.
+    fn drop(&mut self) {
+        self.lines.take();
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CapturedBody, ExchangeTimings};
+    use tempfile::TempDir;
+
+    fn exchange() -> CapturedExchange {
+        CapturedExchange {
+            hostname: "myapp.localhost".to_string(),
+            client_ip: "127.0.0.1".to_string(),
+            backend_port: 4123,
+            method: "GET".to_string(),
+            uri: "/search?q=\"x\"".to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            started_at_ms: 1_700_000_000_123,
+            request_headers: vec![("user-agent".to_string(), "curl/8.0".to_string())],
+            status: Some(200),
+            response_body: CapturedBody {
+                size: 512,
+                ..Default::default()
+            },
+            duration_ms: 2.5,
+            timings: ExchangeTimings {
+                receive_ms: 1.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_common_and_combined() {
+        let entry = AccessEntry::from_exchange(&exchange());
+        let common = format_entry(&entry, AccessLogFormat::Common);
+        assert!(common.starts_with("127.0.0.1 - - ["), "{}", common);
+        assert!(
+            common.ends_with(
+                "] \"GET /search?q=\\\"x\\\" HTTP/1.1\" 200 512 \
+                 host=myapp.localhost backend=4123 duration_ms=3.500\n"
+            ),
+            "{}",
+            common
+        );
+
+        let combined = format_entry(&entry, AccessLogFormat::Combined);
+        assert!(
+            combined.contains(" 200 512 \"-\" \"curl/8.0\" host=myapp.localhost"),
+            "{}",
+            combined
+        );
+    }
+
+    #[test]
+    fn test_websocket_and_failure_markers() {
+        let mut ex = exchange();
+        ex.status = None;
+        ex.error = Some("connection closed".to_string());
+        ex.response_body = CapturedBody::default();
+        let entry = AccessEntry::from_exchange(&ex);
+        assert!(format_entry(&entry, AccessLogFormat::Common).contains(" 502 - host="));
+
+        let entry = AccessEntry {
+            status: 101,
+            websocket: true,
+            ..entry
+        };
+        assert!(format_entry(&entry, AccessLogFormat::Common).ends_with(" upgrade=websocket\n"));
+    }
+
+    #[test]
+    fn test_websocket_entry() {
+        let req = Request::get("/socket")
+            .header("user-agent", "browser")
+            .body(())
+            .unwrap();
+        let entry = AccessEntry::websocket(&req, "chat.localhost", 4200, [127, 0, 0, 1].into());
+        assert_eq!(entry.uri, "/socket");
+        assert_eq!(entry.backend_port, 4200);
+        assert_eq!(entry.user_agent.as_deref(), Some("browser"));
+        assert!(entry.websocket);
+
+        let entry = AccessEntry::request(&req, "unknown.localhost", 0, [127, 0, 0, 1].into());
+        assert_eq!(entry.hostname, "unknown.localhost");
+        assert!(!entry.websocket);
+    }
+
+    #[test]
+    fn test_json() {
+        let line = format_entry(&AccessEntry::from_exchange(&exchange()), AccessLogFormat::Json);
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["time"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(value["host"], "myapp.localhost");
+        assert_eq!(value["backend_port"], 4123);
+        assert_eq!(value["status"], 200);
+        assert_eq!(value["bytes"], 512);
+        assert_eq!(value["duration_ms"], 3.5);
+        assert_eq!(value["user_agent"], "curl/8.0");
+        assert!(value.get("upgrade").is_none());
+    }
+
+    #[test]
+    fn test_writes_to_state_dir() {
+        let temp = TempDir::new().unwrap();
+        let log = AccessLog::open(temp.path(), AccessLogFormat::Json).unwrap();
+        log.log(&AccessEntry::from_exchange(&exchange()));
+        log.log(&AccessEntry::from_exchange(&exchange()));
+        drop(log);
+        let written = std::fs::read_to_string(temp.path().join(ACCESS_LOG_FILE)).unwrap();
+        assert_eq!(written.lines().count(), 2);
+    }
+}
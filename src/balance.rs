@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::types::{Balance, Route};
+
+/// Spreads requests over the instances registered with `--join` at one
+/// hostname and path.
+pub struct Balancer {
+    /// Round-robin position per route name.
+    next: Mutex<HashMap<String, usize>>,
+    /// Requests in flight per backend (see [`Route::target`]), for least-connections.
+    in_flight: Mutex<HashMap<String, usize>>,
+}
+
+/// Counts a request as in flight to a backend until dropped.
+pub struct InFlight {
+    balancer: Arc<Balancer>,
+    target: String,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = self.balancer.in_flight.lock()
+            && let Some(count) = in_flight.get_mut(&self.target)
+        {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.target);
+            }
+        }
+    }
+}
+
+impl Balancer {
+    pub fn new() -> Self {
+        Self {
+            next: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Choose the instance for the next request. Instances for which `usable`
+    /// is false (still starting or unhealthy) are only chosen if no other is.
+    /// The strategy is that of the most recently registered instance setting one.
+    pub fn pick<'a>(
+        &self,
+        instances: &[&'a Route],
+        usable: impl Fn(&Route) -> bool,
+    ) -> Option<&'a Route> {
+        let preferred: Vec<&Route> = instances.iter().copied().filter(|r| usable(r)).collect();
+        let candidates = if preferred.is_empty() {
+            instances
+        } else {
+            &preferred[..]
+        };
+        if candidates.len() <= 1 {
+            return candidates.first().copied();
+        }
+        let strategy = instances
+            .iter()
+            .rev()
+            .find_map(|r| r.balance)
+            .unwrap_or_default();
+        match strategy {
+            Balance::RoundRobin => {
+                let mut next = self.next.lock().ok()?;
+                let turn = next.entry(candidates[0].name()).or_insert(0);
+                let route = candidates[*turn % candidates.len()];
+                *turn = turn.wrapping_add(1);
+                Some(route)
+            }
+            Balance::LeastConn => {
+                let in_flight = self.in_flight.lock().ok()?;
+                candidates
+                    .iter()
+                    .copied()
+                    .min_by_key(|r| in_flight.get(&r.target()).copied().unwrap_or(0))
+            }
+        }
+    }
+
+    pub fn track(self: &Arc<Self>, route: &Route) -> InFlight {
+        let target = route.target();
+        if let Ok(mut in_flight) = self.in_flight.lock() {
+            *in_flight.entry(target.clone()).or_insert(0) += 1;
+        }
+        InFlight {
+            balancer: self.clone(),
+            target,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(port: u16, balance: Option<Balance>) -> Route {
+        Route {
+            hostname: "api.localhost".to_string(),
+            port,
+            pid: u32::from(port),
+            join: true,
+            balance,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_round_robin_skips_unusable() {
+        let balancer = Balancer::new();
+        let routes = [instance(4001, None), instance(4002, None), instance(4003, None)];
+        let instances: Vec<&Route> = routes.iter().collect();
+
+        let ports: Vec<u16> = (0..4)
+            .map(|_| balancer.pick(&instances, |_| true).unwrap().port)
+            .collect();
+        assert_eq!(ports, vec![4001, 4002, 4003, 4001]);
+
+        let ports: Vec<u16> = (0..3)
+            .map(|_| balancer.pick(&instances, |r| r.port != 4002).unwrap().port)
+            .collect();
+        assert!(!ports.contains(&4002), "{:?}", ports);
+
+        // With nothing usable, requests still go somewhere
+        assert!(balancer.pick(&instances, |_| false).is_some());
+        assert!(balancer.pick(&[], |_| true).is_none());
+    }
+
+    #[test]
+    fn test_least_conn_follows_in_flight_requests() {
+        let balancer = Arc::new(Balancer::new());
+        let routes = [instance(4001, None), instance(4002, Some(Balance::LeastConn))];
+        let instances: Vec<&Route> = routes.iter().collect();
+
+        let first = balancer.track(&routes[0]);
+        assert_eq!(balancer.pick(&instances, |_| true).unwrap().port, 4002);
+        let _second = balancer.track(&routes[1]);
+        let _third = balancer.track(&routes[1]);
+        assert_eq!(balancer.pick(&instances, |_| true).unwrap().port, 4001);
+
+        drop(first);
+        assert!(balancer.in_flight.lock().unwrap().get("localhost:4001").is_none());
+    }
+}
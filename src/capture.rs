@@ -0,0 +1,505 @@
+use http_body_util::BodyExt;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::{Request, Response};
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+
+use crate::pool::{ConnectTime, ProxyBody};
+use crate::types::{CapturedBody, CapturedExchange, ExchangeTimings};
+use crate::utils::hostname_matches;
+
+/// Number of exchanges kept across all routes.
+pub const CAPTURE_CAPACITY: usize = 200;
+/// Bytes of each request and response body kept for inspection.
+pub const CAPTURE_BODY_LIMIT: usize = 32 * 1024;
+/// Exchanges buffered for a slow `portless tail` before it starts skipping.
+const LIVE_BUFFER: usize = 64;
+
+#[derive(Debug, Default)]
+struct BodyBuffer {
+    data: Vec<u8>,
+    size: u64,
+}
+
+impl BodyBuffer {
+    fn push(&mut self, chunk: &[u8]) {
+        self.size += chunk.len() as u64;
+        let room = CAPTURE_BODY_LIMIT.saturating_sub(self.data.len());
+        self.data.extend_from_slice(&chunk[..chunk.len().min(room)]);
+    }
+
+    fn captured(&self) -> CapturedBody {
+        CapturedBody {
+            size: self.size,
+            truncated: self.size > self.data.len() as u64,
+            text: String::from_utf8_lossy(&self.data).into_owned(),
+        }
+    }
+}
+
+type OnDone = Box<dyn FnOnce() + Send + Sync>;
+type Observer = Box<dyn Fn(&CapturedExchange) + Send + Sync>;
+
+/// Passes a body through unchanged while copying its first bytes into a buffer.
+/// `on_done` runs once the body ends, fails, or is dropped early.
+struct TeeBody<B> {
+    inner: B,
+    buffer: Arc<Mutex<BodyBuffer>>,
+    on_done: Option<OnDone>,
+}
+
+impl<B> TeeBody<B> {
+    fn done(&mut self) {
+        if let Some(on_done) = self.on_done.take() {
+            on_done();
+        }
+    }
+}
+
+impl<B> Body for TeeBody<B>
+where
+    B: Body<Data = Bytes> + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        let this = self.get_mut();
+        let poll = Pin::new(&mut this.inner).poll_frame(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(frame))) => {
+                if let (Some(data), Ok(mut buffer)) = (frame.data_ref(), this.buffer.lock()) {
+                    buffer.push(data);
+                }
+                // hyper stops polling once a body reports its end, so don't wait for `None`.
+                if this.inner.is_end_stream() {
+                    this.done();
+                }
+            }
+            Poll::Ready(Some(Err(_))) | Poll::Ready(None) => this.done(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B> Drop for TeeBody<B> {
+    fn drop(&mut self) {
+        self.done();
+    }
+}
+
+/// A finished exchange. The kept request body bytes allow it to be replayed.
+pub struct Capture {
+    pub exchange: CapturedExchange,
+    pub request_body: Bytes,
+}
+
+/// Ring buffer of recent exchanges, plus a live feed for `portless tail`.
+pub struct CaptureLog {
+    entries: Mutex<VecDeque<Arc<Capture>>>,
+    capacity: usize,
+    next_id: AtomicU64,
+    live: broadcast::Sender<Arc<Capture>>,
+    /// Sees every completed exchange, unlike lagging `subscribe` receivers.
+    observer: Option<Observer>,
+}
+
+impl CaptureLog {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            next_id: AtomicU64::new(1),
+            live: broadcast::channel(LIVE_BUFFER).0,
+            observer: None,
+        }
+    }
+
+    /// Call `observer` with each exchange as it completes, e.g. to write an access log.
+    pub fn with_observer(
+        mut self,
+        observer: impl Fn(&CapturedExchange) + Send + Sync + 'static,
+    ) -> Self {
+        self.observer = Some(Box::new(observer));
+        self
+    }
+
+    /// Start recording `req`. The returned request must be forwarded in place of
+    /// the original so its body is copied as the backend reads it.
+    pub fn begin<B>(
+        self: &Arc<Self>,
+        hostname: &str,
+        scheme: &str,
+        client_ip: IpAddr,
+        backend_port: u16,
+        req: Request<B>,
+        replay_of: Option<u64>,
+    ) -> (CaptureHandle, Request<ProxyBody>)
+    where
+        B: Body<Data = Bytes, Error = hyper::Error> + Send + Sync + Unpin + 'static,
+    {
+        let exchange = CapturedExchange {
+            id: self.next_id.fetch_add(1, Ordering::SeqCst),
+            hostname: hostname.to_string(),
+            client_ip: client_ip.to_string(),
+            backend_port,
+            scheme: scheme.to_string(),
+            method: req.method().to_string(),
+            uri: req
+                .uri()
+                .path_and_query()
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| "/".to_string()),
+            http_version: format!("{:?}", req.version()),
+            started_at_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            request_headers: header_pairs(req.headers()),
+            replay_of,
+            ..Default::default()
+        };
+
+        let request_buffer = Arc::new(Mutex::new(BodyBuffer::default()));
+        let request_sent = Arc::new(Mutex::new(None));
+        let on_sent: OnDone = {
+            let request_sent = request_sent.clone();
+            Box::new(move || {
+                if let Ok(mut sent) = request_sent.lock() {
+                    *sent = Some(Instant::now());
+                }
+            })
+        };
+        let (parts, body) = req.into_parts();
+        let body = TeeBody {
+            inner: body,
+            buffer: request_buffer.clone(),
+            on_done: Some(on_sent),
+        };
+        let handle = CaptureHandle {
+            log: self.clone(),
+            exchange,
+            started: Instant::now(),
+            request_buffer,
+            request_sent,
+        };
+        (handle, Request::from_parts(parts, body.boxed()))
+    }
+
+    pub fn get(&self, id: u64) -> Option<Arc<Capture>> {
+        let entries = self.entries.lock().ok()?;
+        entries.iter().find(|c| c.exchange.id == id).cloned()
+    }
+
+    /// The last `limit` exchanges for `hostname`, which may be a wildcard, oldest first.
+    pub fn recent(&self, hostname: &str, limit: usize) -> Vec<Arc<Capture>> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        let mut recent: Vec<_> = entries
+            .iter()
+            .rev()
+            .filter(|c| hostname_matches(hostname, &c.exchange.hostname))
+            .take(limit)
+            .cloned()
+            .collect();
+        recent.reverse();
+        recent
+    }
+
+    /// Exchanges for `hostname` that started at or after `since_ms` (Unix epoch), oldest first.
+    pub fn since(&self, hostname: &str, since_ms: u64) -> Vec<Arc<Capture>> {
+        let Ok(entries) = self.entries.lock() else {
+            return Vec::new();
+        };
+        entries
+            .iter()
+            .filter(|c| {
+                hostname_matches(hostname, &c.exchange.hostname)
+                    && c.exchange.started_at_ms >= since_ms
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Exchanges recorded from now on, for every route.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Capture>> {
+        self.live.subscribe()
+    }
+
+    fn push(&self, capture: Capture) {
+        if let Some(observer) = &self.observer {
+            observer(&capture.exchange);
+        }
+        let capture = Arc::new(capture);
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() == self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back(capture.clone());
+        }
+        let _ = self.live.send(capture);
+    }
+}
+
+/// An exchange in flight, completed by [`CaptureHandle::finish`].
+pub struct CaptureHandle {
+    log: Arc<CaptureLog>,
+    exchange: CapturedExchange,
+    started: Instant,
+    request_buffer: Arc<Mutex<BodyBuffer>>,
+    /// When the request body was fully handed to the backend connection.
+    request_sent: Arc<Mutex<Option<Instant>>>,
+}
+
+impl CaptureHandle {
+    pub fn id(&self) -> u64 {
+        self.exchange.id
+    }
+
+    /// Record the backend's answer. The exchange is stored once the response
+    /// body has been passed on to the client.
+    pub fn finish(
+        self,
+        result: Result<Response<ProxyBody>, hyper::Error>,
+    ) -> Result<Response<ProxyBody>, hyper::Error> {
+        let CaptureHandle {
+            log,
+            mut exchange,
+            started,
+            request_buffer,
+            request_sent,
+        } = self;
+        let headers_at = Instant::now();
+        exchange.duration_ms = millis(headers_at - started);
+
+        // The request body may still be streaming (or unread) when the response starts.
+        let sent_at = request_sent
+            .lock()
+            .ok()
+            .and_then(|sent| *sent)
+            .map_or(headers_at, |sent| sent.min(headers_at));
+        let connect = match &result {
+            Ok(resp) => resp.extensions().get::<ConnectTime>().map(|c| c.0),
+            Err(_) => None,
+        };
+        exchange.timings = ExchangeTimings {
+            connect_ms: connect.map(millis),
+            send_ms: millis((sent_at - started).saturating_sub(connect.unwrap_or_default())),
+            wait_ms: millis(headers_at - sent_at),
+            receive_ms: 0.0,
+        };
+
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => {
+                exchange.error = Some(e.to_string());
+                log.push(complete(exchange, &request_buffer, None));
+                return Err(e);
+            }
+        };
+
+        exchange.status = Some(resp.status().as_u16());
+        exchange.response_headers = header_pairs(resp.headers());
+
+        let response_buffer = Arc::new(Mutex::new(BodyBuffer::default()));
+        let on_done = {
+            let response_buffer = response_buffer.clone();
+            Box::new(move || {
+                exchange.timings.receive_ms = millis(headers_at.elapsed());
+                log.push(complete(exchange, &request_buffer, Some(&response_buffer)));
+            })
+        };
+        let (parts, body) = resp.into_parts();
+        let body = TeeBody {
+            inner: body,
+            buffer: response_buffer,
+            on_done: Some(on_done),
+        };
+        Ok(Response::from_parts(parts, body.boxed()))
+    }
+}
+
+fn complete(
+    mut exchange: CapturedExchange,
+    request_buffer: &Mutex<BodyBuffer>,
+    response_buffer: Option<&Mutex<BodyBuffer>>,
+) -> Capture {
+    let mut request_body = Bytes::new();
+    if let Ok(buffer) = request_buffer.lock() {
+        exchange.request_body = buffer.captured();
+        request_body = Bytes::copy_from_slice(&buffer.data);
+    }
+    if let Some(Ok(buffer)) = response_buffer.map(Mutex::lock) {
+        exchange.response_body = buffer.captured();
+    }
+    Capture {
+        exchange,
+        request_body,
+    }
+}
+
+fn millis(d: std::time::Duration) -> f64 {
+    d.as_secs_f64() * 1000.0
+}
+
+fn header_pairs(headers: &hyper::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use std::net::Ipv4Addr;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn full(body: impl Into<Bytes>) -> ProxyBody {
+        Full::new(body.into()).map_err(|e| match e {}).boxed()
+    }
+
+    async fn exchange(log: &Arc<CaptureLog>, host: &str, req_body: Bytes, resp_body: Bytes) -> u64 {
+        let req = Request::post("/hook?x=1")
+            .header("content-type", "application/json")
+            .body(full(req_body))
+            .unwrap();
+        let (handle, req) = log.begin(host, "http", LOCALHOST, 4000, req, None);
+        let id = handle.id();
+        // Stand-in for the backend reading the request
+        req.into_body().collect().await.unwrap();
+        let resp = handle.finish(Ok(Response::new(full(resp_body)))).unwrap();
+        resp.into_body().collect().await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_records_exchange() {
+        let log = Arc::new(CaptureLog::new(CAPTURE_CAPACITY));
+        let mut live = log.subscribe();
+        let id = exchange(&log, "app.localhost", Bytes::from("{\"a\":1}"), Bytes::from("ok")).await;
+
+        let capture = log.get(id).unwrap();
+        let ex = &capture.exchange;
+        assert_eq!(ex.hostname, "app.localhost");
+        assert_eq!(ex.method, "POST");
+        assert_eq!(ex.uri, "/hook?x=1");
+        assert_eq!(ex.status, Some(200));
+        assert_eq!(ex.request_body.text, "{\"a\":1}");
+        assert_eq!(ex.response_body.text, "ok");
+        assert!(ex
+            .request_headers
+            .contains(&("content-type".to_string(), "application/json".to_string())));
+        assert_eq!(capture.request_body, Bytes::from("{\"a\":1}"));
+        assert_eq!(ex.scheme, "http");
+        assert_eq!(ex.client_ip, "127.0.0.1");
+        assert_eq!(ex.backend_port, 4000);
+        assert_eq!(ex.timings.connect_ms, None);
+
+        assert_eq!(live.recv().await.unwrap().exchange.id, id);
+    }
+
+    #[tokio::test]
+    async fn test_truncates_large_bodies() {
+        let log = Arc::new(CaptureLog::new(CAPTURE_CAPACITY));
+        let big = Bytes::from(vec![b'x'; CAPTURE_BODY_LIMIT + 10]);
+        let id = exchange(&log, "app.localhost", big.clone(), big).await;
+
+        let ex = &log.get(id).unwrap().exchange;
+        assert!(ex.request_body.truncated);
+        assert_eq!(ex.request_body.size, (CAPTURE_BODY_LIMIT + 10) as u64);
+        assert_eq!(ex.request_body.text.len(), CAPTURE_BODY_LIMIT);
+        assert!(ex.response_body.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_ring_buffer_evicts_oldest() {
+        let log = Arc::new(CaptureLog::new(2));
+        let first = exchange(&log, "a.localhost", Bytes::new(), Bytes::new()).await;
+        let second = exchange(&log, "b.localhost", Bytes::new(), Bytes::new()).await;
+        let third = exchange(&log, "a.localhost", Bytes::new(), Bytes::new()).await;
+
+        assert!(log.get(first).is_none());
+        assert!(log.get(second).is_some());
+        let recent: Vec<u64> = log
+            .recent("a.localhost", 10)
+            .iter()
+            .map(|c| c.exchange.id)
+            .collect();
+        assert_eq!(recent, vec![third]);
+
+        let started = log.get(third).unwrap().exchange.started_at_ms;
+        assert_eq!(log.since("a.localhost", started).len(), 1);
+        assert!(log.since("a.localhost", started + 1).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_wildcard_hostname_filter() {
+        let log = Arc::new(CaptureLog::new(CAPTURE_CAPACITY));
+        exchange(&log, "acme.shop.localhost", Bytes::new(), Bytes::new()).await;
+        exchange(&log, "shop.localhost", Bytes::new(), Bytes::new()).await;
+        exchange(&log, "eu.beta.shop.localhost", Bytes::new(), Bytes::new()).await;
+
+        let hosts: Vec<String> = log
+            .recent("*.shop.localhost", 10)
+            .iter()
+            .map(|c| c.exchange.hostname.clone())
+            .collect();
+        assert_eq!(hosts, vec!["acme.shop.localhost", "eu.beta.shop.localhost"]);
+        assert_eq!(log.recent("shop.localhost", 10).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_response_is_still_recorded() {
+        let log = Arc::new(CaptureLog::new(CAPTURE_CAPACITY));
+        let (handle, _req) =
+            log.begin("app.localhost", "http", LOCALHOST, 4000, Request::new(full("")), None);
+        let id = handle.id();
+        let resp = handle.finish(Ok(Response::new(full("never read")))).unwrap();
+        assert!(log.get(id).is_none());
+
+        drop(resp);
+        assert_eq!(log.get(id).unwrap().exchange.status, Some(200));
+    }
+
+    #[tokio::test]
+    async fn test_observer_sees_every_exchange() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = {
+            let seen = seen.clone();
+            Arc::new(CaptureLog::new(1).with_observer(move |ex| {
+                seen.lock().unwrap().push(ex.id);
+            }))
+        };
+        let first = exchange(&log, "a.localhost", Bytes::new(), Bytes::new()).await;
+        let second = exchange(&log, "a.localhost", Bytes::new(), Bytes::new()).await;
+        assert_eq!(*seen.lock().unwrap(), vec![first, second]);
+    }
+}
@@ -0,0 +1,256 @@
+//! Control protocol between the CLI and the proxy daemon.
+//!
+//! The proxy listens on a Unix domain socket in the state dir. Each message is a
+//! single line of JSON carrying the protocol version (`v`) and a `type` tag:
+//!
+//! ```text
+//! -> {"v":1,"type":"register","route":{"hostname":"myapp.localhost","port":4123,"pid":42}}
+//! <- {"v":1,"type":"ok"}
+//! ```
+//!
+//! The socket is an optimisation, not a requirement: when it is missing, refuses
+//! connections or speaks another protocol version, callers fall back to the
+//! route files.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::types::{CapturedExchange, Route};
+
+/// Bumped whenever a request or response changes shape incompatibly.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// File name of the control socket inside the state dir.
+pub const CONTROL_SOCKET: &str = "proxy.sock";
+
+/// Upper bound for a single request/response round trip.
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub fn socket_path(state_dir: &Path) -> PathBuf {
+    state_dir.join(CONTROL_SOCKET)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Request {
+    /// Add (or replace) the route for `route.hostname` at `route.path_prefix`.
+    Register { route: Route },
+    /// Remove the route for `hostname` at `path_prefix`; with `pid`, only
+    /// that process's instance.
+    Remove {
+        hostname: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        path_prefix: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pid: Option<u32>,
+    },
+    Status,
+    Version,
+    /// Stop the proxy after replying.
+    Shutdown,
+    /// Stream captured exchanges for `hostname`: the last `backlog` ones, then
+    /// new ones as they complete, each as a `Response::Exchange`.
+    Tail { hostname: String, backlog: usize },
+    /// Re-send the captured request `id` to the route's current backend.
+    Replay { id: u64 },
+    /// Captured exchanges for `hostname` started at or after `since_ms` (Unix epoch).
+    Captures { hostname: String, since_ms: u64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Version { version: String, protocol: u32 },
+    Status(ProxyStatus),
+    Exchange(Box<CapturedExchange>),
+    /// The replayed request was captured as exchange `id`.
+    Replayed { id: u64, status: u16 },
+    Captures { exchanges: Vec<CapturedExchange> },
+    Error { message: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ProxyStatus {
+    pub pid: u32,
+    pub port: u16,
+    pub https: bool,
+    pub version: String,
+    pub uptime_secs: u64,
+    /// Incremented every time the proxy reloads its route table.
+    pub generation: u64,
+    /// Routes whose owning process is still alive.
+    pub routes: Vec<Route>,
+    /// Why each route currently failing its health check is unhealthy, by
+    /// [`Route::target`].
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unhealthy: BTreeMap<String, String>,
+}
+
+/// A protocol message as it appears on the wire.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub v: u32,
+    #[serde(flatten)]
+    pub body: T,
+}
+
+/// Serialize `body` as one newline-terminated line.
+pub fn encode<T: Serialize>(body: T) -> Result<String> {
+    let mut line = serde_json::to_string(&Envelope {
+        v: PROTOCOL_VERSION,
+        body,
+    })?;
+    line.push('\n');
+    Ok(line)
+}
+
+/// Parse one line, rejecting messages from a different protocol version.
+pub fn decode<T: for<'de> Deserialize<'de>>(line: &str) -> Result<T> {
+    let value: serde_json::Value = serde_json::from_str(line.trim())?;
+    let version = value.get("v").and_then(|v| v.as_u64());
+    if version != Some(PROTOCOL_VERSION as u64) {
+        return Err(anyhow!(
+            "unsupported control protocol version {:?} (expected {})",
+            version,
+            PROTOCOL_VERSION
+        ));
+    }
+    let envelope: Envelope<T> = serde_json::from_value(value)?;
+    Ok(envelope.body)
+}
+
+/// Send a single request to the proxy in `state_dir` and wait for the reply.
+///
+/// Errors mean the control socket is unusable; `Response::Error` means the
+/// proxy understood the request but could not carry it out.
+pub fn send(state_dir: &Path, request: &Request) -> Result<Response> {
+    let stream = UnixStream::connect(socket_path(state_dir))?;
+    stream.set_read_timeout(Some(CONTROL_TIMEOUT))?;
+    stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+
+    (&stream).write_all(encode(request)?.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(anyhow!("proxy closed the control connection"));
+    }
+    decode(&line)
+}
+
+/// Send a request that is answered with a stream of responses (e.g. `Tail`) and
+/// pass each one to `on_response` until the proxy closes the connection.
+pub fn stream(
+    state_dir: &Path,
+    request: &Request,
+    mut on_response: impl FnMut(Response),
+) -> Result<()> {
+    let stream = UnixStream::connect(socket_path(state_dir))?;
+    stream.set_write_timeout(Some(CONTROL_TIMEOUT))?;
+    (&stream).write_all(encode(request)?.as_bytes())?;
+
+    for line in BufReader::new(&stream).lines() {
+        match decode(&line?)? {
+            Response::Error { message } => return Err(anyhow!(message)),
+            response => on_response(response),
+        }
+    }
+    Ok(())
+}
+
+/// Like [`send`], but treats `Response::Error` as a failure too.
+pub fn send_ok(state_dir: &Path, request: &Request) -> Result<Response> {
+    match send(state_dir, request)? {
+        Response::Error { message } => Err(anyhow!(message)),
+        response => Ok(response),
+    }
+}
+
+/// Query the proxy status, or `None` if the control socket is unavailable.
+pub fn status(state_dir: &Path) -> Option<ProxyStatus> {
+    match send(state_dir, &Request::Status) {
+        Ok(Response::Status(status)) => Some(status),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_wire_format() {
+        let line = encode(Request::Remove {
+            hostname: "app.localhost".to_string(),
+            path_prefix: None,
+            pid: None,
+        })
+        .unwrap();
+        assert_eq!(
+            line,
+            "{\"v\":1,\"type\":\"remove\",\"hostname\":\"app.localhost\"}\n"
+        );
+
+        let request: Request = decode(&line).unwrap();
+        assert_eq!(
+            request,
+            Request::Remove {
+                hostname: "app.localhost".to_string(),
+                path_prefix: None,
+                pid: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_other_versions() {
+        assert!(decode::<Request>("{\"v\":2,\"type\":\"status\"}").is_err());
+        assert!(decode::<Request>("{\"type\":\"status\"}").is_err());
+        assert_eq!(
+            decode::<Request>("{\"v\":1,\"type\":\"status\"}").unwrap(),
+            Request::Status
+        );
+    }
+
+    #[test]
+    fn test_send_round_trip() {
+        let temp = TempDir::new().unwrap();
+        let listener = UnixListener::bind(socket_path(temp.path())).unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            assert_eq!(decode::<Request>(&line).unwrap(), Request::Version);
+            let reply = encode(Response::Version {
+                version: "1.2.3".to_string(),
+                protocol: PROTOCOL_VERSION,
+            })
+            .unwrap();
+            (&stream).write_all(reply.as_bytes()).unwrap();
+        });
+
+        let response = send(temp.path(), &Request::Version).unwrap();
+        server.join().unwrap();
+        assert_eq!(
+            response,
+            Response::Version {
+                version: "1.2.3".to_string(),
+                protocol: PROTOCOL_VERSION
+            }
+        );
+    }
+
+    #[test]
+    fn test_send_without_proxy() {
+        let temp = TempDir::new().unwrap();
+        assert!(send(temp.path(), &Request::Status).is_err());
+        assert!(status(temp.path()).is_none());
+    }
+}
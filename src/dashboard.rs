@@ -0,0 +1,203 @@
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes, Frame};
+use hyper::header::HeaderValue;
+use hyper::{Response, StatusCode};
+use serde_json::{json, Value};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crate::control::ProxyStatus;
+use crate::pool::ProxyBody;
+use crate::stats::Stats;
+use crate::utils::format_route_url;
+
+/// How often connected dashboards receive a fresh snapshot.
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Response body fed from a channel; the stream ends when the sender is dropped.
+pub struct ChannelBody {
+    rx: mpsc::Receiver<Bytes>,
+}
+
+impl ChannelBody {
+    pub fn new(rx: mpsc::Receiver<Bytes>) -> Self {
+        Self { rx }
+    }
+}
+
+impl Body for ChannelBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, hyper::Error>>> {
+        self.rx.poll_recv(cx).map(|chunk| chunk.map(|c| Ok(Frame::data(c))))
+    }
+}
+
+/// Everything the dashboard shows. `status.routes` holds every registered route,
+/// including ones whose process has exited.
+pub fn snapshot(status: &ProxyStatus, stats: &Stats) -> Value {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let routes: Vec<Value> = status
+        .routes
+        .iter()
+        .map(|r| {
+            let counts = stats.snapshot(&r.name());
+            json!({
+                "hostname": r.name(),
+                "url": format_route_url(r, status.port, status.https),
+                "port": r.port,
+                "target": r.target(),
+                "pid": if r.alias { None } else { Some(r.pid) },
+                "command": r.command,
+                "h2c": r.h2c,
+                "uptime_secs": r.started_at.map(|t| now.saturating_sub(t)),
+                "status": if r.is_alive() { r.state.as_str() } else { "exited" },
+                "health_error": status.unhealthy.get(&r.target()),
+                "stats": counts,
+                "error_rate": counts.recent_error_rate(),
+            })
+        })
+        .collect();
+
+    json!({
+        "proxy": {
+            "version": status.version,
+            "pid": status.pid,
+            "port": status.port,
+            "https": status.https,
+            "uptime_secs": status.uptime_secs,
+            "generation": status.generation,
+        },
+        "routes": routes,
+    })
+}
+
+pub fn page_response() -> Response<ProxyBody> {
+    respond(StatusCode::OK, "text/html; charset=utf-8", DASHBOARD_HTML)
+}
+
+pub fn json_response(value: &Value) -> Response<ProxyBody> {
+    respond(StatusCode::OK, "application/json", value.to_string())
+}
+
+/// Prometheus text exposition format.
+pub fn metrics_response(body: String) -> Response<ProxyBody> {
+    respond(StatusCode::OK, "text/plain; version=0.0.4; charset=utf-8", body)
+}
+
+pub fn not_found_response() -> Response<ProxyBody> {
+    respond(StatusCode::NOT_FOUND, "text/plain", "Not Found")
+}
+
+/// Server-sent events stream that pushes `next()` every [`EVENT_INTERVAL`]
+/// until the client disconnects.
+pub fn events_response<F>(next: F) -> Response<ProxyBody>
+where
+    F: Fn() -> Value + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
+    tokio::spawn(async move {
+        loop {
+            let event = format!("data: {}\n\n", next());
+            if tx.send(Bytes::from(event)).await.is_err() {
+                return;
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(EVENT_INTERVAL) => {}
+                _ = tx.closed() => return,
+            }
+        }
+    });
+
+    let mut resp = Response::new(ChannelBody::new(rx).boxed());
+    let headers = resp.headers_mut();
+    headers.insert("content-type", HeaderValue::from_static("text/event-stream"));
+    headers.insert("cache-control", HeaderValue::from_static("no-cache"));
+    headers.insert("x-portless", HeaderValue::from_static("1"));
+    resp
+}
+
+fn respond(status: StatusCode, content_type: &'static str, body: impl Into<Bytes>) -> Response<ProxyBody> {
+    let mut resp = Response::new(Full::new(body.into()).map_err(|e| match e {}).boxed());
+    *resp.status_mut() = status;
+    resp.headers_mut()
+        .insert("content-type", HeaderValue::from_static(content_type));
+    resp.headers_mut()
+        .insert("x-portless", HeaderValue::from_static("1"));
+    resp
+}
+
+const DASHBOARD_HTML: &str = r#"<!doctype html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <title>portless</title>
+    <style>
+      body { font-family: system-ui; padding: 40px; max-width: 1100px; margin: 0 auto; color: #222; }
+      table { border-collapse: collapse; width: 100%; }
+      th, td { text-align: left; padding: 6px 10px; border-bottom: 1px solid #eee; font-size: 14px; }
+      th { color: #666; font-weight: 600; }
+      td.num { text-align: right; font-variant-numeric: tabular-nums; }
+      code { font-size: 13px; color: #555; }
+      .ready { color: #1a7f37; }
+      .starting { color: #9a6700; }
+      .unhealthy { color: #cf222e; }
+      .exited { color: #cf222e; }
+      .muted { color: #888; }
+    </style>
+  </head>
+  <body>
+    <h1>portless</h1>
+    <p class="muted" id="proxy">Connecting&hellip;</p>
+    <table>
+      <thead>
+        <tr>
+          <th>App</th><th>Target</th><th>PID</th><th>Command</th><th>Uptime</th><th>Status</th>
+          <th>Requests (1m)</th><th>Errors (1m)</th><th>Error rate</th><th>Total</th>
+        </tr>
+      </thead>
+      <tbody id="routes"><tr><td colspan="10" class="muted">No apps running.</td></tr></tbody>
+    </table>
+    <script>
+      const esc = (s) => String(s ?? "").replace(/[&<>"']/g, (c) =>
+        ({ "&": "&amp;", "<": "&lt;", ">": "&gt;", '"': "&quot;", "'": "&#39;" })[c]);
+      const duration = (s) => s == null ? "" :
+        s < 60 ? s + "s" : s < 3600 ? Math.floor(s / 60) + "m" : Math.floor(s / 3600) + "h " + Math.floor(s % 3600 / 60) + "m";
+
+      function render(data) {
+        const p = data.proxy;
+        document.getElementById("proxy").textContent =
+          `proxy v${p.version} (pid ${p.pid}) on port ${p.port}${p.https ? " with HTTPS" : ""}, up ${duration(p.uptime_secs)}, route generation ${p.generation}`;
+        const rows = data.routes.map((r) => `<tr>
+          <td><a href="${esc(r.url)}">${esc(r.hostname)}</a></td>
+          <td>${esc(r.target)}${r.h2c ? " <span class=muted>h2c</span>" : ""}</td>
+          <td>${r.pid ?? "alias"}</td>
+          <td><code>${esc(r.command)}</code></td>
+          <td>${duration(r.uptime_secs)}</td>
+          <td class="${r.status}" title="${esc(r.health_error)}">${r.status}</td>
+          <td class="num">${r.stats.recent_requests}</td>
+          <td class="num">${r.stats.recent_errors}</td>
+          <td class="num">${(r.error_rate * 100).toFixed(1)}%</td>
+          <td class="num">${r.stats.requests}</td>
+        </tr>`);
+        document.getElementById("routes").innerHTML = rows.length ? rows.join("") :
+          '<tr><td colspan="10" class="muted">No apps running.</td></tr>';
+      }
+
+      const events = new EventSource("/events");
+      events.onmessage = (e) => render(JSON.parse(e.data));
+      events.onerror = () => { document.getElementById("proxy").textContent = "Disconnected, retrying…"; };
+    </script>
+  </body>
+</html>
+"#;
@@ -0,0 +1,229 @@
+//! HTTP Archive (HAR 1.2) export of captured exchanges.
+//!
+//! Spec: <http://www.softwareishard.com/blog/har-12-spec/>
+
+use serde_json::{json, Value};
+
+use crate::types::{CapturedBody, CapturedExchange};
+use crate::utils::format_rfc3339;
+
+pub const HAR_VERSION: &str = "1.2";
+
+/// Build a HAR log from exchanges, in the order given.
+pub fn build_har(exchanges: &[CapturedExchange]) -> Value {
+    json!({
+        "log": {
+            "version": HAR_VERSION,
+            "creator": {
+                "name": "portless",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "pages": [],
+            "entries": exchanges.iter().map(entry).collect::<Vec<_>>(),
+        }
+    })
+}
+
+fn entry(ex: &CapturedExchange) -> Value {
+    let t = &ex.timings;
+    let connect = t.connect_ms.unwrap_or(-1.0);
+    let total = t.connect_ms.unwrap_or(0.0) + t.send_ms + t.wait_ms + t.receive_ms;
+
+    let mut request = json!({
+        "method": ex.method,
+        "url": url(ex),
+        "httpVersion": ex.http_version,
+        "cookies": [],
+        "headers": headers(&ex.request_headers),
+        "queryString": query_string(&ex.uri),
+        "headersSize": -1,
+        "bodySize": ex.request_body.size,
+    });
+    if ex.request_body.size > 0 {
+        request["postData"] = json!({
+            "mimeType": content_type(&ex.request_headers),
+            "text": ex.request_body.text,
+        });
+        if let Some(comment) = truncation_comment(&ex.request_body) {
+            request["postData"]["comment"] = json!(comment);
+        }
+    }
+
+    let mut content = json!({
+        "size": ex.response_body.size,
+        "mimeType": content_type(&ex.response_headers),
+        "text": ex.response_body.text,
+    });
+    if let Some(comment) = truncation_comment(&ex.response_body) {
+        content["comment"] = json!(comment);
+    }
+    let status = ex.status.unwrap_or(0);
+    let status_text = hyper::StatusCode::from_u16(status)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or("");
+
+    let mut entry = json!({
+        "startedDateTime": format_rfc3339(ex.started_at_ms),
+        "time": total,
+        "request": request,
+        "response": {
+            "status": status,
+            "statusText": status_text,
+            "httpVersion": ex.http_version,
+            "cookies": [],
+            "headers": headers(&ex.response_headers),
+            "content": content,
+            "redirectURL": header(&ex.response_headers, "location").unwrap_or(""),
+            "headersSize": -1,
+            "bodySize": ex.response_body.size,
+        },
+        "cache": {},
+        "timings": {
+            "blocked": -1,
+            "dns": -1,
+            "connect": connect,
+            "ssl": -1,
+            "send": t.send_ms,
+            "wait": t.wait_ms,
+            "receive": t.receive_ms,
+        },
+        "serverIPAddress": "127.0.0.1",
+        "_id": ex.id,
+    });
+    if let Some(error) = &ex.error {
+        entry["_error"] = json!(error);
+    }
+    if let Some(original) = ex.replay_of {
+        entry["_replayOf"] = json!(original);
+    }
+    entry
+}
+
+/// The URL as the client requested it, including the port from its Host header.
+fn url(ex: &CapturedExchange) -> String {
+    let host = header(&ex.request_headers, "host").unwrap_or(&ex.hostname);
+    format!("{}://{}{}", ex.scheme, host, ex.uri)
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn headers(headers: &[(String, String)]) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn content_type(headers: &[(String, String)]) -> &str {
+    header(headers, "content-type").unwrap_or("")
+}
+
+fn query_string(uri: &str) -> Vec<Value> {
+    let Some((_, query)) = uri.split_once('?') else {
+        return Vec::new();
+    };
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            json!({ "name": name, "value": value })
+        })
+        .collect()
+}
+
+fn truncation_comment(body: &CapturedBody) -> Option<String> {
+    body.truncated.then(|| {
+        format!(
+            "truncated by portless: {} of {} bytes captured",
+            body.text.len(),
+            body.size
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::ExchangeTimings;
+
+    fn exchange() -> CapturedExchange {
+        CapturedExchange {
+            id: 7,
+            hostname: "api.localhost".to_string(),
+            scheme: "http".to_string(),
+            method: "POST".to_string(),
+            uri: "/hook?source=stripe&retry".to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            started_at_ms: 1_700_000_000_123,
+            request_headers: vec![
+                ("host".to_string(), "api.localhost:1355".to_string()),
+                ("content-type".to_string(), "application/json".to_string()),
+            ],
+            request_body: CapturedBody {
+                size: 9,
+                truncated: false,
+                text: "{\"a\":1}".to_string(),
+            },
+            status: Some(404),
+            response_headers: vec![("content-type".to_string(), "text/plain".to_string())],
+            response_body: CapturedBody {
+                size: 100_000,
+                truncated: true,
+                text: "nope".to_string(),
+            },
+            duration_ms: 4.0,
+            timings: ExchangeTimings {
+                connect_ms: Some(1.0),
+                send_ms: 0.5,
+                wait_ms: 2.5,
+                receive_ms: 3.0,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_har_entry() {
+        let har = build_har(&[exchange()]);
+        assert_eq!(har["log"]["version"], "1.2");
+        let entry = &har["log"]["entries"][0];
+
+        assert_eq!(entry["startedDateTime"], "2023-11-14T22:13:20.123Z");
+        assert_eq!(entry["time"], 7.0);
+        assert_eq!(entry["request"]["url"], "http://api.localhost:1355/hook?source=stripe&retry");
+        assert_eq!(entry["request"]["queryString"][0]["name"], "source");
+        assert_eq!(entry["request"]["queryString"][0]["value"], "stripe");
+        assert_eq!(entry["request"]["queryString"][1]["name"], "retry");
+        assert_eq!(entry["request"]["postData"]["mimeType"], "application/json");
+        assert_eq!(entry["response"]["status"], 404);
+        assert_eq!(entry["response"]["statusText"], "Not Found");
+        assert_eq!(entry["response"]["content"]["size"], 100_000);
+        assert!(entry["response"]["content"]["comment"].is_string());
+        assert_eq!(entry["timings"]["connect"], 1.0);
+        assert_eq!(entry["timings"]["wait"], 2.5);
+        assert_eq!(entry["_id"], 7);
+    }
+
+    #[test]
+    fn test_har_entry_reused_connection_and_failure() {
+        let mut ex = exchange();
+        ex.timings.connect_ms = None;
+        ex.status = None;
+        ex.error = Some("connection closed".to_string());
+        ex.request_body = CapturedBody::default();
+
+        let entry = &build_har(&[ex])["log"]["entries"][0];
+        assert_eq!(entry["timings"]["connect"], -1.0);
+        assert_eq!(entry["time"], 6.0);
+        assert_eq!(entry["response"]["status"], 0);
+        assert_eq!(entry["_error"], "connection closed");
+        assert!(entry["request"].get("postData").is_none());
+    }
+}
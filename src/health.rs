@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time::Duration;
+
+use crate::pool::{self, BackendKey};
+use crate::ready::http_status_over;
+use crate::types::{HealthCheck, Route};
+
+/// How often every route's backend is checked.
+pub const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// A check that takes longer than this fails, which is how hung apps are caught.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// Consecutive failed checks before a route counts as unhealthy.
+const UNHEALTHY_THRESHOLD: u32 = 2;
+
+#[derive(Debug, Default)]
+struct Health {
+    failures: u32,
+    last_error: Option<String>,
+}
+
+/// Results of the periodic health checks, per backend.
+pub struct HealthMonitor {
+    backends: Mutex<HashMap<(String, String), Health>>,
+}
+
+impl HealthMonitor {
+    pub fn new() -> Self {
+        Self {
+            backends: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Why `route` is unhealthy, or `None` while it passes its checks.
+    pub fn failure(&self, route: &Route) -> Option<String> {
+        let backends = self.backends.lock().ok()?;
+        let health = backends.get(&(route.hostname.clone(), route.target()))?;
+        if health.failures >= UNHEALTHY_THRESHOLD {
+            health.last_error.clone()
+        } else {
+            None
+        }
+    }
+
+    /// Check every route once, concurrently, and log routes that change state.
+    pub async fn check_all(&self, routes: &[Route]) {
+        let mut checks = JoinSet::new();
+        for route in routes {
+            let route = route.clone();
+            checks.spawn(async move {
+                let result = check(&route).await;
+                (route, result)
+            });
+        }
+        while let Some(Ok((route, result))) = checks.join_next().await {
+            let was_healthy = self.failure(&route).is_none();
+            self.record(&route, result);
+            match (was_healthy, self.failure(&route)) {
+                (true, Some(reason)) => {
+                    eprintln!("portless proxy: {} is unhealthy: {}", route.name(), reason)
+                }
+                (false, None) => eprintln!("portless proxy: {} is healthy again", route.name()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Forget backends that no route points at anymore.
+    pub fn retain(&self, routes: &[Route]) {
+        if let Ok(mut backends) = self.backends.lock() {
+            backends.retain(|(host, target), _| {
+                routes
+                    .iter()
+                    .any(|r| &r.hostname == host && &r.target() == target)
+            });
+        }
+    }
+
+    fn record(&self, route: &Route, result: Result<(), String>) {
+        let Ok(mut backends) = self.backends.lock() else {
+            return;
+        };
+        let health = backends
+            .entry((route.hostname.clone(), route.target()))
+            .or_default();
+        match result {
+            Ok(()) => *health = Health::default(),
+            Err(e) => {
+                health.failures += 1;
+                health.last_error = Some(e);
+            }
+        }
+    }
+}
+
+/// Run `route`'s health check once.
+pub async fn check(route: &Route) -> Result<(), String> {
+    let backend = BackendKey::for_route(route);
+    let probe = async {
+        let stream = pool::connect(&backend)
+            .await
+            .map_err(|e| format!("{} does not accept connections ({})", route.target(), e))?;
+        match &route.health_check {
+            Some(HealthCheck::Tcp) => Ok(()),
+            // Any status will do: a hung app accepts the connection but never answers.
+            None => http_status_over(stream, &backend, "/")
+                .await
+                .map(drop)
+                .ok_or_else(|| "GET / got no HTTP response".to_string()),
+            Some(HealthCheck::Http { path, status }) => {
+                match (http_status_over(stream, &backend, path).await, status) {
+                    (None, _) => Err(format!("GET {} got no HTTP response", path)),
+                    (Some(got), Some(want)) if got != *want => {
+                        Err(format!("GET {} returned {} (expected {})", path, got, want))
+                    }
+                    (Some(got), None) if !(200..400).contains(&got) => {
+                        Err(format!("GET {} returned {}", path, got))
+                    }
+                    _ => Ok(()),
+                }
+            }
+        }
+    };
+    tokio::time::timeout(HEALTH_CHECK_TIMEOUT, probe)
+        .await
+        .unwrap_or_else(|_| {
+            Err(format!(
+                "no answer within {}s",
+                HEALTH_CHECK_TIMEOUT.as_secs()
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Empty;
+    use hyper::body::{Bytes, Incoming};
+    use hyper::{Request, Response, StatusCode};
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn route(port: u16, health_check: Option<HealthCheck>) -> Route {
+        Route {
+            hostname: "app.localhost".to_string(),
+            port,
+            pid: 1,
+            health_check,
+            ..Default::default()
+        }
+    }
+
+    /// Answer every request with `status`; with `None`, accept but never answer.
+    async fn server(status: Option<u16>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut hung = Vec::new();
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                match status {
+                    Some(status) => {
+                        let reply = format!("HTTP/1.1 {} X\r\ncontent-length: 0\r\n\r\n", status);
+                        let _ = stream.write_all(reply.as_bytes()).await;
+                    }
+                    None => hung.push(stream),
+                }
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_http_checks() {
+        let ok = server(Some(200)).await;
+        let failing = server(Some(500)).await;
+        let http = |status| {
+            Some(HealthCheck::Http {
+                path: "/healthz".to_string(),
+                status,
+            })
+        };
+
+        assert!(check(&route(ok, http(None))).await.is_ok());
+        assert!(check(&route(ok, http(Some(200)))).await.is_ok());
+        assert_eq!(
+            check(&route(ok, http(Some(204)))).await.unwrap_err(),
+            "GET /healthz returned 200 (expected 204)"
+        );
+        assert_eq!(
+            check(&route(failing, http(None))).await.unwrap_err(),
+            "GET /healthz returned 500"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hung_backend_times_out() {
+        let hung = server(None).await;
+        let check_http = Some(HealthCheck::Http {
+            path: "/".to_string(),
+            status: None,
+        });
+        // Accepting connections is all a TCP check can see
+        assert!(check(&route(hung, Some(HealthCheck::Tcp))).await.is_ok());
+        assert!(check(&route(hung, None)).await.unwrap_err().contains("no answer"));
+        assert!(check(&route(hung, check_http)).await.unwrap_err().contains("no answer"));
+
+        // By default any answer will do, even an error page.
+        let not_found = server(Some(404)).await;
+        assert!(check(&route(not_found, None)).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_h2c_backend_is_checked_over_http2() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(|req: Request<Incoming>| async move {
+                        let status = if req.uri().path() == "/healthz" { 204 } else { 404 };
+                        let mut resp = Response::new(Empty::<Bytes>::new());
+                        *resp.status_mut() = StatusCode::from_u16(status).unwrap();
+                        Ok::<_, hyper::Error>(resp)
+                    });
+                    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        let h2c = |health_check| Route {
+            h2c: true,
+            ..route(port, health_check)
+        };
+
+        assert!(check(&h2c(None)).await.is_ok());
+        let healthz = HealthCheck::Http {
+            path: "/healthz".to_string(),
+            status: Some(204),
+        };
+        assert!(check(&h2c(Some(healthz))).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_after_repeated_failures() {
+        let monitor = HealthMonitor::new();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = route(listener.local_addr().unwrap().port(), None);
+        drop(listener);
+
+        monitor.check_all(std::slice::from_ref(&closed)).await;
+        assert!(monitor.failure(&closed).is_none());
+        monitor.check_all(std::slice::from_ref(&closed)).await;
+        assert!(monitor.failure(&closed).unwrap().contains("does not accept connections"));
+
+        monitor.record(&closed, Ok(()));
+        assert!(monitor.failure(&closed).is_none());
+
+        monitor.record(&closed, Err("down".to_string()));
+        monitor.record(&closed, Err("down".to_string()));
+        monitor.retain(&[]);
+        assert!(monitor.failure(&closed).is_none());
+    }
+}
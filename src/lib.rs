@@ -1,6 +1,10 @@
 // Public API for the portless library (used by integration tests)
 
+pub mod control;
+pub mod har;
 pub mod routes;
+pub mod tls;
+pub mod trust;
 pub mod types;
 pub mod utils;
 
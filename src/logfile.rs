@@ -0,0 +1,131 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::fd::{AsFd, AsRawFd};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// stdout/stderr of the background proxy.
+pub const PROXY_LOG_FILE: &str = "proxy.log";
+/// Size at which a log file is rotated.
+pub const LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+/// Rotated files kept next to the live one (`<name>.1` is the newest).
+pub const LOG_KEEP: usize = 3;
+
+/// `path` with a rotation suffix, e.g. `proxy.log.2`.
+pub fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Shift `path` to `path.1`, `path.1` to `path.2` and so on, dropping the
+/// oldest beyond `keep`.
+pub fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    if keep == 0 {
+        return fs::remove_file(path);
+    }
+    let _ = fs::remove_file(numbered(path, keep));
+    for n in (1..keep).rev() {
+        let _ = fs::rename(numbered(path, n), numbered(path, n + 1));
+    }
+    fs::rename(path, numbered(path, 1))
+}
+
+/// Rotate `path` if it has grown past [`LOG_MAX_BYTES`]. Returns whether it did.
+pub fn rotate_if_large(path: &Path) -> io::Result<bool> {
+    match fs::metadata(path) {
+        Ok(meta) if meta.len() >= LOG_MAX_BYTES => rotate(path, LOG_KEEP).map(|()| true),
+        _ => Ok(false),
+    }
+}
+
+/// An append-only file that rotates itself once it reaches `max_bytes`.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_bytes: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_bytes: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            len,
+            max_bytes,
+        })
+    }
+
+    /// Append `line` in a single write, rotating first if it would not fit.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.len > 0 && self.len + line.len() as u64 > self.max_bytes {
+            rotate(&self.path, LOG_KEEP)?;
+            *self = Self::open(self.path.clone(), self.max_bytes)?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.len += line.len() as u64;
+        Ok(())
+    }
+}
+
+/// Rotate `proxy.log` once it is too large, and point our stdout/stderr at the
+/// fresh file. Does nothing unless stderr is that file, i.e. when the proxy was
+/// started in the background.
+pub fn rotate_proxy_log(state_dir: &Path) -> io::Result<bool> {
+    let path = state_dir.join(PROXY_LOG_FILE);
+    let Ok(log) = fs::metadata(&path) else {
+        return Ok(false);
+    };
+    if log.len() < LOG_MAX_BYTES {
+        return Ok(false);
+    }
+    let stderr = File::from(io::stderr().as_fd().try_clone_to_owned()?).metadata()?;
+    if (stderr.dev(), stderr.ino()) != (log.dev(), log.ino()) {
+        return Ok(false);
+    }
+
+    rotate(&path, LOG_KEEP)?;
+    let file = OpenOptions::new().create(true).append(true).open(&path)?;
+    // SAFETY: dup2 only swaps what our own stdout/stderr descriptors point to.
+    unsafe {
+        nix::libc::dup2(file.as_raw_fd(), nix::libc::STDOUT_FILENO);
+        nix::libc::dup2(file.as_raw_fd(), nix::libc::STDERR_FILENO);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_rotate_keeps_newest() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("access.log");
+        for generation in 1..=4 {
+            fs::write(&path, generation.to_string()).unwrap();
+            rotate(&path, 2).unwrap();
+        }
+        assert!(!path.exists());
+        assert_eq!(fs::read_to_string(numbered(&path, 1)).unwrap(), "4");
+        assert_eq!(fs::read_to_string(numbered(&path, 2)).unwrap(), "3");
+        assert!(!numbered(&path, 3).exists());
+    }
+
+    #[test]
+    fn test_rotating_file_rotates_at_limit() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("access.log");
+        let mut file = RotatingFile::open(path.clone(), 10).unwrap();
+        file.write_line("12345\n").unwrap();
+        file.write_line("67890\n").unwrap();
+        file.write_line("abc\n").unwrap();
+
+        assert_eq!(fs::read_to_string(numbered(&path, 1)).unwrap(), "12345\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "67890\nabc\n");
+    }
+}
@@ -1,28 +1,72 @@
+mod access_log;
+mod balance;
+mod capture;
+mod control;
+mod dashboard;
+mod har;
+mod health;
+mod logfile;
+mod metrics;
+mod output;
+mod pool;
+mod project;
 mod proxy;
+mod ready;
 mod routes;
+mod startup;
+mod stats;
+mod supervisor;
+mod tls;
+mod trust;
 mod types;
 mod utils;
+mod watcher;
 
 use anyhow::{anyhow, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use colored::Colorize;
+use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::io::{BufRead, Write as IoWrite};
+use std::io::{BufRead, IsTerminal, Write as IoWrite};
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::Stdio;
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 use tokio::process::Command as TokioCommand;
 use tokio::signal::unix::{signal, SignalKind};
-
+use tokio::sync::Notify;
+use tokio::task::JoinSet;
+
+use access_log::AccessLogFormat;
+use output::AppOutput;
+use pool::BackendKey;
+use project::Project;
+use ready::ReadyProbe;
 use routes::RouteStore;
-use types::Route;
+use supervisor::RestartPolicy;
+use types::{Balance, HealthCheck, Route, RouteState, Upstream};
 use utils::{
-    discover_state, find_free_port, find_pid_on_port, format_url, is_proxy_running, parse_hostname,
-    resolve_state_dir, signal_exit_code, DEFAULT_PROXY_PORT, PRIVILEGED_PORT_THRESHOLD,
+    discover_state, find_free_port, find_pid_on_port, format_route_url, format_url,
+    https_marker_path, is_https_enabled, is_proxy_running, parse_hostname, parse_path_prefix,
+    resolve_state_dir, signal_exit_code, socket_path, DEFAULT_PROXY_PORT,
+    PRIVILEGED_PORT_THRESHOLD,
 };
 
+/// How long `portless down` waits for the apps to exit.
+const DOWN_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long `portless <name>` waits for the app to exit after passing on a signal.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the app's last output is awaited once it has exited.
+const OUTPUT_DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+/// How often `portless logs --follow` looks for new output.
+const LOGS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// How much longer than the app's startup timeout `portless start -d` waits
+/// for it to be ready, e.g. for the proxy to start first.
+const DETACHED_START_MARGIN: Duration = Duration::from_secs(10);
+
 #[derive(Parser)]
 #[command(
     name = "portless",
@@ -52,6 +96,69 @@ struct Cli {
     /// Proxy port (default: 1355, or $PORTLESS_PORT)
     #[arg(short = 'p', long, global = true)]
     port: Option<u16>,
+
+    #[command(flatten)]
+    run: RunOptions,
+}
+
+/// Options for `portless <name> <cmd...>`; they must come before the app name.
+#[derive(Args, Clone, Default)]
+struct RunOptions {
+    /// Talk HTTP/2 with prior knowledge (h2c) to the app, e.g. for gRPC servers
+    #[arg(long)]
+    h2c: bool,
+    /// Hold requests for up to DURATION while the app starts listening
+    /// (default: 30s, 0 to disable)
+    #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration)]
+    startup_timeout: Option<Duration>,
+    /// How to tell the app is ready: tcp (default), http:/path or log:<regex>
+    #[arg(long, value_name = "PROBE", value_parser = ready::parse_probe)]
+    ready: Option<ReadyProbe>,
+    /// How the proxy checks the app every few seconds once it is ready: any
+    /// answer to GET / (default), tcp (only that it accepts connections, which
+    /// a hung app still does), http:/path or http:/path=STATUS
+    #[arg(long, value_name = "CHECK")]
+    health_check: Option<HealthCheck>,
+    /// Serve alongside instances already running under this name instead of
+    /// replacing them; requests are spread over all of them
+    #[arg(long)]
+    join: bool,
+    /// How requests are spread over the instances of a joined name
+    #[arg(long, value_enum, value_name = "STRATEGY", requires = "join")]
+    balance: Option<Balance>,
+    /// With a name like `myapp/api`, forward `/api/users` to the app as `/users`
+    #[arg(long)]
+    strip_prefix: bool,
+    /// Have the app listen on a Unix socket, passed in PORTLESS_SOCKET, instead of a port
+    #[arg(long)]
+    socket: bool,
+    /// Inject the port flags of this framework even if the command does not
+    /// start with it, e.g. `npm run dev`
+    #[arg(
+        long,
+        value_name = "FRAMEWORK",
+        value_parser = clap::builder::PossibleValuesParser::new(FRAMEWORKS.iter().map(|(n, _)| *n))
+    )]
+    framework: Option<String>,
+    /// Start the app again when it exits: on-failure (crashes only) or always.
+    /// Its name and port stay reserved meanwhile; restarts back off and stop
+    /// when it keeps crashing
+    #[arg(long, value_enum, value_name = "POLICY")]
+    restart: Option<RestartPolicy>,
+    /// Open the app in the browser once it is ready
+    #[arg(long)]
+    open: bool,
+    /// Start each line of the app's output with the time and the app's name,
+    /// in a colour of its own, to tell apps sharing a terminal apart
+    #[arg(long)]
+    prefix: bool,
+    /// Pad names in prefixes to this width, to line up the output of several apps
+    #[arg(long, value_name = "WIDTH", requires = "prefix", hide = true)]
+    prefix_width: Option<usize>,
+    /// Set an environment variable for the app; `portless up` passes the
+    /// `env` of portless.toml this way, so `portless restart` keeps it
+    #[arg(long = "env", value_name = "KEY=VALUE", value_parser = utils::parse_env_var, hide = true)]
+    env: Vec<(String, String)>,
 }
 
 #[derive(Subcommand)]
@@ -61,8 +168,129 @@ enum Commands {
         #[command(subcommand)]
         action: ProxyAction,
     },
+    /// Trust the local HTTPS CA (system store and Firefox/Chromium NSS databases)
+    Trust {
+        /// Print the changes without applying them
+        #[arg(long)]
+        dry_run: bool,
+        /// Only write the CA certificate to FILE instead of installing it
+        #[arg(long, value_name = "FILE")]
+        export: Option<PathBuf>,
+        /// Export in DER instead of PEM
+        #[arg(long, requires = "export")]
+        der: bool,
+    },
+    /// Remove the local HTTPS CA from the trust stores
+    Untrust {
+        /// Print the changes without applying them
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// List active routes
     List,
+    /// Route a name to a port served by something portless did not start,
+    /// e.g. a Docker container, or to a remote host; kept until `portless unalias`
+    Alias {
+        /// Name, e.g. `db-admin` for db-admin.localhost (paths and `*.` wildcards work too)
+        name: String,
+        /// Port on 127.0.0.1 to forward to, or an upstream as `host:port`,
+        /// `http://host[:port]` or `https://host[:port]`
+        target: String,
+        /// Talk HTTP/2 with prior knowledge (h2c) to the target
+        #[arg(long)]
+        h2c: bool,
+        /// How the proxy checks the target: any answer to GET / (default), tcp
+        /// (only that it accepts connections), http:/path or http:/path=STATUS
+        #[arg(long, value_name = "CHECK")]
+        health_check: Option<HealthCheck>,
+        /// Take the name over from an app currently running under it
+        #[arg(long)]
+        force: bool,
+    },
+    /// Remove a route created with `portless alias`
+    Unalias {
+        /// Name the alias was created with
+        name: String,
+    },
+    /// Start the apps declared in portless.toml (found in the current
+    /// directory or a parent) and wait for them
+    Up {
+        /// Only start these apps
+        apps: Vec<String>,
+        /// Pass the apps' output through as is, without prefixing each line
+        /// with the time and the app's name
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Run an app like `portless <name> <command>`, or in the background with -d
+    Start {
+        /// Run in the background and return once the app is ready; read its
+        /// output with `portless logs <name>`
+        #[arg(short = 'd', long)]
+        detach: bool,
+        /// Options, name and command, as for `portless <name> <command>`
+        #[arg(
+            value_name = "ARGS",
+            required = true,
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        args: Vec<String>,
+    },
+    /// Stop an app, wherever it was started from
+    Stop {
+        /// App name, e.g. `myapp` for myapp.localhost or `myapp/api`
+        name: String,
+    },
+    /// Stop an app and start it again with the same command in the same
+    /// directory; it always comes back in the background, as with `start -d`
+    Restart {
+        /// App name, e.g. `myapp` for myapp.localhost or `myapp/api`
+        name: String,
+    },
+    /// Stop the apps declared in portless.toml
+    Down {
+        /// Only stop these apps
+        apps: Vec<String>,
+    },
+    /// Show the output of an app, including one started in another terminal
+    Logs {
+        /// App name, e.g. `myapp` for myapp.localhost or `myapp/api`
+        name: String,
+        /// Keep printing the app's output as it is written
+        #[arg(short, long)]
+        follow: bool,
+        /// Only show output from this far back, e.g. 30s, 5m, 1h
+        #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration)]
+        since: Option<Duration>,
+    },
+    /// Stream requests hitting an app as they happen
+    Tail {
+        /// App name, e.g. `myapp` for myapp.localhost
+        name: String,
+        /// Number of recent requests to show first
+        #[arg(short = 'n', long, default_value_t = 10)]
+        lines: usize,
+        /// Also print headers and bodies
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Re-send a captured request (see `portless tail`) to the app
+    Replay {
+        /// Request id as shown by `portless tail`
+        id: u64,
+    },
+    /// Export captured requests to an app as an HTTP Archive (HAR) file
+    Har {
+        /// App name, e.g. `myapp` for myapp.localhost
+        name: String,
+        /// Only include requests from this far back, e.g. 30s, 5m, 1h
+        #[arg(long, value_name = "DURATION", value_parser = utils::parse_duration)]
+        since: Option<Duration>,
+        /// Write to FILE instead of stdout
+        #[arg(short, long, value_name = "FILE")]
+        output: Option<PathBuf>,
+    },
 }
 
 #[derive(Subcommand)]
@@ -72,6 +300,14 @@ enum ProxyAction {
         /// Run in foreground instead of as a daemon
         #[arg(long)]
         foreground: bool,
+        /// Also serve HTTPS using certificates from a generated local CA
+        /// (or set $PORTLESS_HTTPS=1)
+        #[arg(long)]
+        https: bool,
+        /// Access log format for <state dir>/access.log
+        /// (default: combined, or $PORTLESS_ACCESS_LOG)
+        #[arg(long, value_enum)]
+        access_log: Option<AccessLogFormat>,
     },
     /// Stop the running proxy server
     Stop,
@@ -109,16 +345,116 @@ async fn main() -> Result<()> {
             let state_dir = resolve_state_dir(proxy_port);
 
             match action {
-                ProxyAction::Start { foreground } => {
-                    cmd_proxy_start(proxy_port, state_dir, foreground).await
+                ProxyAction::Start {
+                    foreground,
+                    https,
+                    access_log,
+                } => {
+                    let https = https || env_flag("PORTLESS_HTTPS");
+                    let access_log = access_log
+                        .or_else(|| {
+                            env::var("PORTLESS_ACCESS_LOG")
+                                .ok()
+                                .and_then(|v| AccessLogFormat::from_str(&v, true).ok())
+                        })
+                        .unwrap_or(AccessLogFormat::Combined);
+                    cmd_proxy_start(proxy_port, state_dir, foreground, https, access_log).await
                 }
                 ProxyAction::Stop => cmd_proxy_stop(proxy_port, state_dir).await,
             }
         }
+        Some(Commands::Trust {
+            dry_run,
+            export,
+            der,
+        }) => {
+            let (state_dir, _) = discover_state();
+            cmd_trust(state_dir, dry_run, export, der)
+        }
+        Some(Commands::Untrust { dry_run }) => {
+            let (state_dir, _) = discover_state();
+            cmd_untrust(state_dir, dry_run)
+        }
         Some(Commands::List) => {
             let (state_dir, proxy_port) = discover_state();
             cmd_list(state_dir, proxy_port)
         }
+        Some(Commands::Alias {
+            name,
+            target,
+            h2c,
+            health_check,
+            force,
+        }) => {
+            let (state_dir, proxy_port) = discover_state();
+            let (port, upstream) = parse_alias_target(&target)?;
+            let route = Route {
+                port,
+                h2c,
+                health_check,
+                alias: true,
+                upstream,
+                ..Default::default()
+            };
+            cmd_alias(state_dir, proxy_port, &name, route, force)
+        }
+        Some(Commands::Unalias { name }) => {
+            let (state_dir, _) = discover_state();
+            cmd_unalias(state_dir, &name)
+        }
+        Some(Commands::Up { apps, raw }) => {
+            let (_, proxy_port) = discover_state();
+            cmd_up(proxy_port, &apps, raw).await
+        }
+        Some(Commands::Start { detach, args }) => {
+            let (state_dir, proxy_port) = discover_state();
+            if detach {
+                start_detached(&state_dir, proxy_port, &args, &env::current_dir()?)
+            } else {
+                let (name, cmd, opts) = parse_run_args(&args)?;
+                cmd_run(name, cmd, opts, args, proxy_port, state_dir).await
+            }
+        }
+        Some(Commands::Stop { name }) => {
+            let (state_dir, _) = discover_state();
+            cmd_stop(state_dir, &name)
+        }
+        Some(Commands::Restart { name }) => {
+            let (state_dir, proxy_port) = discover_state();
+            cmd_restart(state_dir, proxy_port, &name)
+        }
+        Some(Commands::Down { apps }) => {
+            let (state_dir, _) = discover_state();
+            cmd_down(state_dir, &apps)
+        }
+        Some(Commands::Logs {
+            name,
+            follow,
+            since,
+        }) => {
+            let (state_dir, _) = discover_state();
+            cmd_logs(state_dir, &name, follow, since)
+        }
+        Some(Commands::Tail {
+            name,
+            lines,
+            verbose,
+        }) => {
+            let (state_dir, _) = discover_state();
+            cmd_tail(state_dir, &name, lines, verbose)
+        }
+        Some(Commands::Replay { id }) => {
+            let (state_dir, _) = discover_state();
+            cmd_replay(state_dir, id)
+        }
+        Some(Commands::Har {
+            name,
+            since,
+            output,
+        }) => {
+            let (state_dir, _) = discover_state();
+            cmd_har(state_dir, &name, since, output)
+        }
         None => {
             // Skip portless if PORTLESS=0 or PORTLESS=skip
             let portless_env = env::var("PORTLESS").unwrap_or_default();
@@ -135,7 +471,11 @@ async fn main() -> Result<()> {
             if name.is_empty() {
                 eprintln!("{}", "Usage: portless <name> <command...>".yellow());
                 eprintln!("       portless proxy start|stop");
-                eprintln!("       portless list");
+                eprintln!("       portless trust|untrust");
+                eprintln!("       portless list | alias <name> <port|url> | unalias <name>");
+                eprintln!("       portless start [-d] <name> <command...> | stop | restart <name>");
+                eprintln!("       portless up | down | logs <name>");
+                eprintln!("       portless tail <name> | replay <id> | har <name>");
                 std::process::exit(1);
             }
             if cmd.is_empty() {
@@ -147,12 +487,19 @@ async fn main() -> Result<()> {
             }
 
             let (state_dir, proxy_port) = discover_state();
-            cmd_run(name, cmd, proxy_port, state_dir).await
+            let args = env::args().skip(1).collect();
+            cmd_run(name, cmd, cli.run, args, proxy_port, state_dir).await
         }
     }
 }
 
-async fn cmd_proxy_start(port: u16, state_dir: PathBuf, foreground: bool) -> Result<()> {
+async fn cmd_proxy_start(
+    port: u16,
+    state_dir: PathBuf,
+    foreground: bool,
+    https: bool,
+    access_log: AccessLogFormat,
+) -> Result<()> {
     if is_proxy_running(port) {
         if foreground {
             // Foreground mode used internally by daemon fork; exit silently if already running
@@ -190,15 +537,27 @@ async fn cmd_proxy_start(port: u16, state_dir: PathBuf, foreground: bool) -> Res
             "{}",
             format!("\nportless proxy v{}\n", env!("CARGO_PKG_VERSION")).bold().blue()
         );
-        proxy::run_proxy(port, state_dir).await
+        proxy::run_proxy(proxy::ProxyConfig {
+            port,
+            state_dir,
+            https,
+            access_log,
+        })
+        .await
     } else {
-        daemonize_proxy(port, state_dir)
+        daemonize_proxy(port, state_dir, https, access_log)
     }
 }
 
-fn daemonize_proxy(port: u16, state_dir: PathBuf) -> Result<()> {
+fn daemonize_proxy(
+    port: u16,
+    state_dir: PathBuf,
+    https: bool,
+    access_log: AccessLogFormat,
+) -> Result<()> {
     fs::create_dir_all(&state_dir)?;
-    let log_path = state_dir.join("proxy.log");
+    let log_path = state_dir.join(logfile::PROXY_LOG_FILE);
+    logfile::rotate_if_large(&log_path)?;
 
     let log_file = fs::OpenOptions::new()
         .create(true)
@@ -210,6 +569,12 @@ fn daemonize_proxy(port: u16, state_dir: PathBuf) -> Result<()> {
     let mut cmd = std::process::Command::new(&exe);
     cmd.args(["proxy", "start", "--foreground"]);
     cmd.args(["-p", &port.to_string()]);
+    if https {
+        cmd.arg("--https");
+    }
+    if let Some(format) = access_log.to_possible_value() {
+        cmd.args(["--access-log", format.get_name()]);
+    }
     cmd.stdin(Stdio::null());
     cmd.stdout(Stdio::from(log_file));
     cmd.stderr(Stdio::from(log_file2));
@@ -233,6 +598,12 @@ fn daemonize_proxy(port: u16, state_dir: PathBuf) -> Result<()> {
         thread::sleep(Duration::from_millis(utils::WAIT_FOR_PROXY_INTERVAL_MS));
         if is_proxy_running(port) {
             println!("{}", format!("Proxy started on port {}", port).green());
+            if https {
+                println!(
+                    "{}",
+                    "HTTPS enabled. Run `portless trust` to avoid browser warnings.".dimmed()
+                );
+            }
             return Ok(());
         }
     }
@@ -256,6 +627,12 @@ async fn cmd_proxy_stop(port: u16, state_dir: PathBuf) -> Result<()> {
     let needs_sudo = port < PRIVILEGED_PORT_THRESHOLD;
     let sudo_hint = if needs_sudo { "sudo " } else { "" };
 
+    // The proxy removes its own state files when asked over the control socket.
+    if control::send_ok(&state_dir, &control::Request::Shutdown).is_ok() {
+        println!("{}", "Proxy stopped.".green());
+        return Ok(());
+    }
+
     if !pid_path.exists() {
         if is_proxy_running(port) {
             println!("{}", format!("PID file is missing but port {} is still in use.", port).yellow());
@@ -266,6 +643,7 @@ async fn cmd_proxy_stop(port: u16, state_dir: PathBuf) -> Result<()> {
                 ) {
                     Ok(_) => {
                         let _ = fs::remove_file(&port_path);
+                        let _ = fs::remove_file(https_marker_path(&state_dir));
                         println!("{}", format!("Killed process {}. Proxy stopped.", pid).green());
                     }
                     Err(nix::errno::Errno::EPERM) => {
@@ -303,6 +681,7 @@ async fn cmd_proxy_stop(port: u16, state_dir: PathBuf) -> Result<()> {
         println!("{}", "Proxy process is no longer running. Cleaning up stale files.".yellow());
         let _ = fs::remove_file(&pid_path);
         let _ = fs::remove_file(&port_path);
+        let _ = fs::remove_file(https_marker_path(&state_dir));
         return Ok(());
     }
 
@@ -328,6 +707,7 @@ async fn cmd_proxy_stop(port: u16, state_dir: PathBuf) -> Result<()> {
         Ok(_) => {
             let _ = fs::remove_file(&pid_path);
             let _ = fs::remove_file(&port_path);
+            let _ = fs::remove_file(https_marker_path(&state_dir));
             println!("{}", "Proxy stopped.".green());
         }
         Err(nix::errno::Errno::EPERM) => {
@@ -345,9 +725,93 @@ async fn cmd_proxy_stop(port: u16, state_dir: PathBuf) -> Result<()> {
     Ok(())
 }
 
+fn cmd_trust(state_dir: PathBuf, dry_run: bool, export: Option<PathBuf>, der: bool) -> Result<()> {
+    let ca_path = tls::ca_cert_path(&state_dir);
+    // A dry run leaves the state dir alone, including a CA not generated yet.
+    let ca = if dry_run {
+        if !ca_path.exists() {
+            println!("Would generate a local CA in {}", tls::ca_dir(&state_dir).display());
+        }
+        None
+    } else {
+        Some(tls::LocalCa::load_or_create(&state_dir)?)
+    };
+
+    if let Some(out) = export {
+        let format = if der { "DER" } else { "PEM" };
+        if let Some(ca) = ca {
+            if der {
+                fs::write(&out, ca.cert_der())?;
+            } else {
+                fs::write(&out, ca.cert_pem())?;
+            }
+            println!("{}", format!("Wrote CA certificate ({}) to {}", format, out.display()).green());
+        } else {
+            println!("Would write the CA certificate ({}) to {}", format, out.display());
+        }
+        return Ok(());
+    }
+
+    let plan = trust::install_plan(&ca_path, &trust::TrustEnv::detect());
+    apply_trust_plan(&plan, dry_run)?;
+    if dry_run {
+        return Ok(());
+    }
+    if plan.steps.is_empty() {
+        return Err(anyhow!(
+            "The local CA was not installed in any trust store; import {} by hand",
+            ca_path.display()
+        ));
+    }
+    println!("{}", "Local CA is now trusted. Restart your browser to pick it up.".green());
+    Ok(())
+}
+
+fn cmd_untrust(state_dir: PathBuf, dry_run: bool) -> Result<()> {
+    let plan = trust::uninstall_plan(&trust::TrustEnv::detect());
+    if plan.steps.is_empty() && plan.warnings.is_empty() {
+        println!("{}", "The local CA is not installed in any known trust store.".yellow());
+        return Ok(());
+    }
+    apply_trust_plan(&plan, dry_run)?;
+    if !dry_run {
+        println!("{}", "Local CA removed from the trust stores.".green());
+        println!(
+            "{}",
+            format!("The CA itself is kept in {}", tls::ca_dir(&state_dir).display()).dimmed()
+        );
+    }
+    Ok(())
+}
+
+fn apply_trust_plan(plan: &trust::TrustPlan, dry_run: bool) -> Result<()> {
+    for warning in &plan.warnings {
+        eprintln!("{}", warning.yellow());
+    }
+    if dry_run {
+        if plan.steps.is_empty() {
+            println!("{}", "Nothing to do.".dimmed());
+        }
+        for step in &plan.steps {
+            println!("Would run: {}", step.to_string().cyan());
+        }
+        return Ok(());
+    }
+    for step in &plan.steps {
+        println!("{}", format!("$ {}", step).dimmed());
+    }
+    trust::run_steps(&plan.steps)
+}
+
 fn cmd_list(state_dir: PathBuf, proxy_port: u16) -> Result<()> {
-    let store = RouteStore::new(state_dir)?;
-    let routes = store.load(false)?;
+    let status = control::status(&state_dir);
+    let (routes, https) = match &status {
+        Some(status) => (status.routes.clone(), status.https),
+        None => (
+            RouteStore::new(state_dir.clone())?.load(false)?,
+            is_https_enabled(&state_dir),
+        ),
+    };
 
     if routes.is_empty() {
         println!("{}", "No active routes.".yellow());
@@ -357,28 +821,706 @@ fn cmd_list(state_dir: PathBuf, proxy_port: u16) -> Result<()> {
 
     println!("{}", "\nActive routes:\n".bold().blue());
     for route in &routes {
-        let url = format_url(&route.hostname, proxy_port);
+        let url = format_route_url(route, proxy_port, https);
+        let protocol = if route.h2c { "  h2c" } else { "" };
+        let state = match route.state {
+            RouteState::Starting => route.state.as_str().yellow(),
+            RouteState::Ready => route.state.as_str().green(),
+            RouteState::Unhealthy => route.state.as_str().red(),
+        };
+        let owner = if route.alias {
+            "(alias)".magenta()
+        } else {
+            format!("(pid {})", route.pid).dimmed()
+        };
         println!(
-            "  {}  {}  {}  {}",
+            "  {}  {}  {}  {}  {}{}",
             url.cyan(),
             "->".dimmed(),
-            format!("localhost:{}", route.port).white(),
-            format!("(pid {})", route.pid).dimmed()
+            route.target().white(),
+            state,
+            owner,
+            protocol.dimmed()
         );
+        if let Some(reason) = status.as_ref().and_then(|s| s.unhealthy.get(&route.target())) {
+            println!("      {}", reason.dimmed());
+        }
     }
     println!();
+    if let Some(status) = status {
+        println!(
+            "{}",
+            format!(
+                "Proxy v{} (pid {}), up {}, route generation {}",
+                status.version,
+                status.pid,
+                format_uptime(status.uptime_secs),
+                status.generation
+            )
+            .dimmed()
+        );
+        println!();
+    }
+
+    Ok(())
+}
+
+/// Register `route` (a template holding the port and options) as an alias for `name`.
+fn cmd_alias(
+    state_dir: PathBuf,
+    proxy_port: u16,
+    name: &str,
+    route: Route,
+    force: bool,
+) -> Result<()> {
+    let route = Route {
+        hostname: parse_hostname(name)?,
+        path_prefix: parse_path_prefix(name)?,
+        ..route
+    };
+    let store = RouteStore::new(state_dir.clone())?;
+    let mount = route.path_prefix.as_deref();
+    if let Some(app) = store
+        .load(false)?
+        .into_iter()
+        .find(|r| !r.alias && r.is_mounted_at(&route.hostname, mount))
+        && !force
+    {
+        return Err(anyhow!(
+            "{} is served by a running app (pid {}); stop it first or pass --force",
+            route.name(),
+            app.pid
+        ));
+    }
+    register_route(&store, &state_dir, route.clone())?;
+
+    let url = format_route_url(&route, proxy_port, is_https_enabled(&state_dir));
+    println!(
+        "{}",
+        format!("{}  ->  {}  (alias)", url, route.target()).green()
+    );
+    let addr = SocketAddr::from(([127, 0, 0, 1], route.port));
+    if route.upstream.is_none()
+        && std::net::TcpStream::connect_timeout(&addr, Duration::from_millis(500)).is_err()
+    {
+        println!(
+            "{}",
+            format!("Nothing is listening on port {} yet.", route.port).yellow()
+        );
+    }
+    if !is_proxy_running(proxy_port) {
+        println!("{}", "Start the proxy with: portless proxy start".dimmed());
+    }
+    Ok(())
+}
+
+/// A port number, or anything else as an upstream.
+fn parse_alias_target(target: &str) -> Result<(u16, Option<Upstream>)> {
+    match target.parse::<u16>() {
+        Ok(0) => Err(anyhow!("Port must be between 1 and 65535")),
+        Ok(port) => Ok((port, None)),
+        Err(_) => {
+            let upstream: Upstream = target.parse()?;
+            Ok((upstream.port, Some(upstream)))
+        }
+    }
+}
+
+fn cmd_unalias(state_dir: PathBuf, name: &str) -> Result<()> {
+    let hostname = parse_hostname(name)?;
+    let path_prefix = parse_path_prefix(name)?;
+    let store = RouteStore::new(state_dir.clone())?;
+    let Some(alias) = store
+        .load(false)?
+        .into_iter()
+        .find(|r| r.alias && r.is_mounted_at(&hostname, path_prefix.as_deref()))
+    else {
+        return Err(anyhow!("No alias named {}", name));
+    };
+    // Aliases are the routes without a process, so PID 0 leaves apps alone.
+    deregister_route(&store, &state_dir, &alias.hostname, path_prefix.as_deref(), 0);
+    println!(
+        "{}",
+        format!("Removed alias {} ({})", alias.name(), alias.target()).green()
+    );
+    Ok(())
+}
+
+/// Load the portless.toml nearest to the current directory.
+fn find_project() -> Result<(PathBuf, Project)> {
+    let cwd = env::current_dir()?;
+    let path = project::find(&cwd).ok_or_else(|| {
+        anyhow!(
+            "No {} in {} or any parent directory",
+            project::PROJECT_FILE,
+            cwd.display()
+        )
+    })?;
+    let project = project::load(&path)?;
+    Ok((path, project))
+}
+
+/// Run each app of the project as `portless <name> <command>` and wait until
+/// all of them have exited. Ctrl-C stops them all.
+async fn cmd_up(proxy_port: u16, names: &[String], raw: bool) -> Result<()> {
+    use nix::sys::signal::Signal::{SIGINT, SIGTERM};
+
+    let (path, project) = find_project()?;
+    let apps = project.select(names)?;
+    let width = apps.iter().map(|app| app.route_name().len()).max().unwrap_or(0);
+
+    // Check every app's options before starting any of them.
+    let mut runs = Vec::new();
+    for app in apps {
+        let mut args = app.run_args()?;
+        if !raw {
+            args.splice(0..0, ["--prefix".to_string(), format!("--prefix-width={}", width)]);
+        }
+        parse_run_args(&args)
+            .map_err(|e| anyhow!("App {} in {}: {}", app.route_name(), path.display(), e))?;
+        let dir = app.dir(&project.root);
+        if !dir.is_dir() {
+            return Err(anyhow!(
+                "App {}: directory {} does not exist",
+                app.route_name(),
+                dir.display()
+            ));
+        }
+        runs.push((app, args, dir));
+    }
+
+    // Started once here, so the apps don't all race to start it.
+    if !is_proxy_running(proxy_port) {
+        if proxy_port < PRIVILEGED_PORT_THRESHOLD {
+            return Err(anyhow!(
+                "Proxy is not running; start it first with: sudo portless proxy start -p {}",
+                proxy_port
+            ));
+        }
+        println!("{}", "Starting proxy...".yellow());
+        let status = std::process::Command::new(env::current_exe()?)
+            .args(["proxy", "start"])
+            .status()?;
+        if !status.success() || !is_proxy_running(proxy_port) {
+            return Err(anyhow!("Failed to start the proxy; try `portless proxy start`"));
+        }
+    }
+
+    let exe = env::current_exe()?;
+    let mut running = JoinSet::new();
+    let mut pids = HashMap::new();
+    for (app, args, dir) in runs {
+        let name = app.route_name();
+        let mut child = TokioCommand::new(&exe)
+            .args(&args)
+            .current_dir(&dir)
+            .stdin(Stdio::null())
+            .spawn()
+            .map_err(|e| anyhow!("Failed to start {}: {}", name, e))?;
+        pids.insert(name.clone(), child.id().unwrap_or(0));
+        running.spawn(async move {
+            let status = child.wait().await;
+            (name, status)
+        });
+    }
+
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut stopping = false;
+    let mut failed = false;
+    loop {
+        let sig = tokio::select! {
+            exited = running.join_next() => {
+                let Some(exited) = exited else { break };
+                let Ok((name, status)) = exited else { continue };
+                pids.remove(&name);
+                // `portless` exits like this when stopped, e.g. by `portless down`
+                let stopped = [SIGINT, SIGTERM].map(signal_exit_code);
+                match status.ok().and_then(|s| s.code()) {
+                    Some(0) => println!("{}", format!("{} exited", name).dimmed()),
+                    Some(code) if stopping || stopped.contains(&code) => {
+                        println!("{}", format!("{} stopped", name).dimmed())
+                    }
+                    code => {
+                        failed = true;
+                        let code = code.map_or("a signal".to_string(), |c| format!("code {}", c));
+                        eprintln!("{}", format!("{} exited with {}", name, code).red());
+                    }
+                }
+                continue;
+            }
+            _ = sigint.recv() => SIGINT,
+            _ = sigterm.recv() => SIGTERM,
+        };
+        // Each app's `portless` forwards the signal and deregisters its route.
+        stopping = true;
+        for pid in pids.values() {
+            forward_signal(*pid, sig);
+        }
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Stop the project's apps, wherever they were started from, by signalling
+/// the `portless` process that owns each of their routes.
+fn cmd_down(state_dir: PathBuf, names: &[String]) -> Result<()> {
+    let (path, project) = find_project()?;
+    let routes = RouteStore::new(state_dir)?.load(false)?;
+
+    let mut stopping = Vec::new();
+    for app in project.select(names)? {
+        let name = app.route_name();
+        let hostname = parse_hostname(&name)?;
+        let path_prefix = parse_path_prefix(&name)?;
+        stopping.extend(
+            routes
+                .iter()
+                .filter(|r| !r.alias && r.is_mounted_at(&hostname, path_prefix.as_deref()))
+                .cloned(),
+        );
+    }
+    if stopping.is_empty() {
+        println!("{}", format!("None of the apps in {} are running.", path.display()).yellow());
+        return Ok(());
+    }
+
+    stop_routes(&stopping)?;
+    println!("{}", format!("Stopped {} app(s).", stopping.len()).green());
+    Ok(())
+}
+
+/// Split `args`, as given to `portless <name> <command>`, into the name, the
+/// command and the options.
+fn parse_run_args(args: &[String]) -> Result<(String, Vec<String>, RunOptions)> {
+    let argv = std::iter::once("portless").chain(args.iter().map(String::as_str));
+    let cli = Cli::try_parse_from(argv).map_err(|e| {
+        let message = e.to_string();
+        let message = message.lines().next().unwrap_or_default();
+        anyhow!("{}", message.trim_start_matches("error: "))
+    })?;
+    match (cli.command, cli.name) {
+        (None, Some(name)) if !cli.cmd.is_empty() => Ok((name, cli.cmd, cli.run)),
+        _ => Err(anyhow!("Expected an app name and a command, e.g. `myapp npm run dev`")),
+    }
+}
+
+/// Ask the `portless` processes running `routes` to stop, and wait for them.
+/// Apps started with `portless start -d` lead their own process group, which
+/// is signalled as a whole so nothing the app spawned is left behind.
+fn stop_routes(routes: &[Route]) -> Result<()> {
+    use nix::sys::signal::{killpg, Signal::SIGTERM};
+    use nix::unistd::{getpgid, Pid};
+
+    for route in routes {
+        println!("{}", format!("Stopping {} (pid {})", route.name(), route.pid).dimmed());
+        let pid = Pid::from_raw(route.pid as i32);
+        if getpgid(Some(pid)) == Ok(pid) {
+            let _ = killpg(pid, SIGTERM);
+        } else {
+            forward_signal(route.pid, SIGTERM);
+        }
+    }
+
+    let deadline = std::time::Instant::now() + DOWN_TIMEOUT;
+    while routes.iter().any(|r| routes::is_pid_alive(r.pid)) {
+        if std::time::Instant::now() >= deadline {
+            return Err(anyhow!(
+                "Some apps did not stop within {}s",
+                DOWN_TIMEOUT.as_secs()
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    Ok(())
+}
+
+/// Routes of the apps `portless` runs under `name`.
+fn app_routes(state_dir: &std::path::Path, name: &str) -> Result<Vec<Route>> {
+    let hostname = parse_hostname(name)?;
+    let path_prefix = parse_path_prefix(name)?;
+    let routes: Vec<Route> = RouteStore::new(state_dir.to_path_buf())?
+        .load(false)?
+        .into_iter()
+        .filter(|r| r.is_mounted_at(&hostname, path_prefix.as_deref()))
+        .collect();
+    if routes.iter().any(|r| r.alias) {
+        return Err(anyhow!(
+            "{} is an alias; remove it with `portless unalias {}`",
+            name,
+            name
+        ));
+    }
+    if routes.is_empty() {
+        return Err(anyhow!("No app is running as {}", name));
+    }
+    Ok(routes)
+}
+
+fn cmd_stop(state_dir: PathBuf, name: &str) -> Result<()> {
+    let routes = app_routes(&state_dir, name)?;
+    stop_routes(&routes)?;
+    println!("{}", format!("Stopped {}.", name).green());
+    Ok(())
+}
+
+fn cmd_restart(state_dir: PathBuf, proxy_port: u16, name: &str) -> Result<()> {
+    let routes = app_routes(&state_dir, name)?;
+    if routes.iter().any(|r| r.args.is_empty() || r.cwd.is_none()) {
+        return Err(anyhow!(
+            "{} was started by an older portless; stop it and start it again by hand",
+            name
+        ));
+    }
+    stop_routes(&routes)?;
+    // Each joined instance comes back as one.
+    for route in &routes {
+        let cwd = route.cwd.as_deref().unwrap_or(std::path::Path::new("/"));
+        start_detached(&state_dir, proxy_port, &without_prefix_options(&route.args)?, cwd)?;
+    }
+    Ok(())
+}
+
+/// `args` for `portless <name> <command>` without `--prefix` and
+/// `--prefix-width`, which only matter in a terminal shared with other apps.
+fn without_prefix_options(args: &[String]) -> Result<Vec<String>> {
+    // The command is the tail of `args`; the name and options before it may
+    // come in any order.
+    let (_, cmd, _) = parse_run_args(args)?;
+    let (options, rest) = args.split_at(args.len() - cmd.len());
+    let mut kept = Vec::new();
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--prefix" => {}
+            "--prefix-width" => {
+                options.next();
+            }
+            o if o.starts_with("--prefix-width=") => {}
+            _ => kept.push(option.clone()),
+        }
+    }
+    kept.extend_from_slice(rest);
+    Ok(kept)
+}
+
+/// Run `portless <args>` in `cwd` in the background, in a session of its own
+/// like the proxy, and wait until the app it starts is ready.
+fn start_detached(
+    state_dir: &std::path::Path,
+    proxy_port: u16,
+    args: &[String],
+    cwd: &std::path::Path,
+) -> Result<()> {
+    let (name, _, opts) = parse_run_args(args)?;
+    let mut cmd = std::process::Command::new(env::current_exe()?);
+    cmd.args(args)
+        .current_dir(cwd)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    {
+        use std::os::unix::process::CommandExt;
+        unsafe {
+            cmd.pre_exec(|| {
+                nix::libc::setsid();
+                Ok(())
+            });
+        }
+    }
+    let mut child = cmd
+        .spawn()
+        .map_err(|e| anyhow!("Failed to start {}: {}", name, e))?;
+    let pid = child.id();
+    println!("{}", format!("Starting {} in the background (pid {})...", name, pid).dimmed());
+
+    let store = RouteStore::new(state_dir.to_path_buf())?;
+    let timeout = opts.startup_timeout.unwrap_or(startup::STARTUP_TIMEOUT) + DETACHED_START_MARGIN;
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        thread::sleep(Duration::from_millis(utils::WAIT_FOR_PROXY_INTERVAL_MS));
+        if std::time::Instant::now() >= deadline {
+            return Err(anyhow!(
+                "{} is not ready after {}s; it is still starting in the background. Check \
+                 `portless logs {}`, or stop it with `portless stop {}`",
+                name,
+                timeout.as_secs(),
+                name,
+                name
+            ));
+        }
+        if let Some(status) = child.try_wait()? {
+            return Err(anyhow!(
+                "{} exited while starting ({}); run it without -d to see why, or check \
+                 `portless logs {}`",
+                name,
+                status,
+                name
+            ));
+        }
+        let Some(route) = store.load(false)?.into_iter().find(|r| r.pid == pid) else {
+            continue;
+        };
+        match route.state {
+            RouteState::Starting => continue,
+            RouteState::Ready => {}
+            RouteState::Unhealthy => eprintln!(
+                "{}",
+                format!("{} is not ready yet; requests may fail", name).yellow()
+            ),
+        }
+        let url = format_route_url(&route, proxy_port, is_https_enabled(state_dir));
+        println!("{}", format!("\n  -> {}\n", url).cyan().bold());
+        println!(
+            "{}",
+            format!(
+                "Read its output with `portless logs {}`, stop it with `portless stop {}`",
+                name, name
+            )
+            .dimmed()
+        );
+        return Ok(());
+    }
+}
+
+/// Print the app's log file, oldest first, then with `follow` whatever it
+/// writes next.
+fn cmd_logs(
+    state_dir: PathBuf,
+    name: &str,
+    follow: bool,
+    since: Option<Duration>,
+) -> Result<()> {
+    let hostname = parse_hostname(name)?;
+    let path_prefix = parse_path_prefix(name)?;
+    let path = output::app_log_path(&state_dir, &hostname, path_prefix.as_deref());
+    if !follow && !path.exists() {
+        return Err(anyhow!("No output of {} has been logged ({})", name, path.display()));
+    }
+    let since_ms = since.map(|d| unix_now_ms().saturating_sub(d.as_millis() as u64));
+
+    let print = |line: &str| match output::parse_log_line(line) {
+        Some((time, _)) if since_ms.is_some_and(|since| time < since) => {}
+        Some((time, text)) => println!("{} {}", utils::format_clock(time).dimmed(), text),
+        None => println!("{}", line),
+    };
+    // Rotated files only matter when asked for a time span that may reach into them.
+    if since.is_some() {
+        for rotated in output::rotated_logs(&path) {
+            fs::read_to_string(&rotated)?.lines().for_each(print);
+        }
+    }
+    let mut reader = output::LogReader::new(path);
+    loop {
+        reader.read_lines()?.iter().for_each(|line| print(line));
+        if !follow {
+            return Ok(());
+        }
+        thread::sleep(LOGS_POLL_INTERVAL);
+    }
+}
+
+fn cmd_tail(state_dir: PathBuf, name: &str, backlog: usize, verbose: bool) -> Result<()> {
+    let hostname = parse_hostname(name)?;
+    require_control_socket(&state_dir)?;
+    println!("{}", format!("Tailing requests to {} (Ctrl+C to stop)\n", hostname).dimmed());
+
+    let request = control::Request::Tail { hostname, backlog };
+    control::stream(&state_dir, &request, |response| {
+        if let control::Response::Exchange(exchange) = response {
+            print_exchange(&exchange, verbose);
+        }
+    })?;
+    println!("{}", "Proxy stopped.".yellow());
+    Ok(())
+}
 
+fn cmd_replay(state_dir: PathBuf, id: u64) -> Result<()> {
+    require_control_socket(&state_dir)?;
+    match control::send_ok(&state_dir, &control::Request::Replay { id })? {
+        control::Response::Replayed { id: new_id, status } => {
+            println!(
+                "{}",
+                format!("Replayed #{} as #{}: {}", id, new_id, status_label(Some(status))).green()
+            );
+            Ok(())
+        }
+        other => Err(anyhow!("Unexpected response from proxy: {:?}", other)),
+    }
+}
+
+fn cmd_har(
+    state_dir: PathBuf,
+    name: &str,
+    since: Option<Duration>,
+    output: Option<PathBuf>,
+) -> Result<()> {
+    let hostname = parse_hostname(name)?;
+    let since_ms = since.map_or(0, |d| unix_now_ms().saturating_sub(d.as_millis() as u64));
+    require_control_socket(&state_dir)?;
+
+    let request = control::Request::Captures {
+        hostname: hostname.clone(),
+        since_ms,
+    };
+    let exchanges = match control::send_ok(&state_dir, &request)? {
+        control::Response::Captures { exchanges } => exchanges,
+        other => return Err(anyhow!("Unexpected response from proxy: {:?}", other)),
+    };
+    let har = serde_json::to_string_pretty(&har::build_har(&exchanges))?;
+
+    match output {
+        Some(path) => {
+            fs::write(&path, har)?;
+            eprintln!(
+                "{}",
+                format!(
+                    "Wrote {} request{} to {} to {}",
+                    exchanges.len(),
+                    if exchanges.len() == 1 { "" } else { "s" },
+                    hostname,
+                    path.display()
+                )
+                .green()
+            );
+        }
+        None => println!("{}", har),
+    }
+    Ok(())
+}
+
+/// The request inspector lives in the proxy, so these commands need it running.
+fn require_control_socket(state_dir: &std::path::Path) -> Result<()> {
+    if control::send(state_dir, &control::Request::Version).is_err() {
+        return Err(anyhow!(
+            "Proxy is not running (or is too old to inspect requests).\nStart an app with: portless <name> <command>"
+        ));
+    }
     Ok(())
 }
 
+fn status_label(status: Option<u16>) -> String {
+    let Some(code) = status else {
+        return "failed".to_string();
+    };
+    let reason = hyper::StatusCode::from_u16(code)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or("");
+    format!("{} {}", code, reason).trim_end().to_string()
+}
+
+fn print_exchange(exchange: &types::CapturedExchange, verbose: bool) {
+    let status = match exchange.status {
+        Some(code) if code >= 500 => status_label(Some(code)).red(),
+        Some(code) if code >= 400 => status_label(Some(code)).yellow(),
+        Some(code) => status_label(Some(code)).green(),
+        None => status_label(None).red(),
+    };
+    let replay = exchange
+        .replay_of
+        .map(|id| format!("  (replay of #{})", id))
+        .unwrap_or_default();
+    println!(
+        "{} {} {} {} {} {}{}",
+        format!("#{}", exchange.id).bold(),
+        utils::format_clock(exchange.started_at_ms).dimmed(),
+        exchange.method.cyan(),
+        exchange.uri,
+        status,
+        format!("{:.0}ms", exchange.duration_ms).dimmed(),
+        replay.dimmed()
+    );
+    if let Some(error) = &exchange.error {
+        println!("    {}", error.red());
+    }
+    if !verbose {
+        return;
+    }
+
+    for (name, value) in &exchange.request_headers {
+        println!("    {} {}: {}", ">".dimmed(), name, value);
+    }
+    print_body(&exchange.request_body);
+    for (name, value) in &exchange.response_headers {
+        println!("    {} {}: {}", "<".dimmed(), name, value);
+    }
+    print_body(&exchange.response_body);
+    println!();
+}
+
+fn print_body(body: &types::CapturedBody) {
+    if body.size == 0 {
+        return;
+    }
+    println!();
+    for line in body.text.lines() {
+        println!("      {}", line);
+    }
+    if body.truncated {
+        println!(
+            "      {}",
+            format!("… ({} of {} bytes shown)", body.text.len(), body.size).dimmed()
+        );
+    }
+    println!();
+}
+
+fn unix_now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn format_uptime(secs: u64) -> String {
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, (secs % 3600) / 60),
+    }
+}
+
 async fn cmd_run(
     name: String,
     mut cmd: Vec<String>,
+    opts: RunOptions,
+    args: Vec<String>,
     proxy_port: u16,
     state_dir: PathBuf,
 ) -> Result<()> {
     let hostname = parse_hostname(&name)?;
-    let app_url = format_url(&hostname, proxy_port);
+    let path_prefix = parse_path_prefix(&name)?;
+    if opts.strip_prefix && path_prefix.is_none() {
+        return Err(anyhow!(
+            "--strip-prefix needs a name with a path, e.g. `portless {}/api <command>`",
+            hostname.trim_end_matches(".localhost")
+        ));
+    }
+
+    let store = RouteStore::new(state_dir.clone())?;
+    if let Some(alias) = store
+        .load(false)?
+        .into_iter()
+        .find(|r| r.alias && r.is_mounted_at(&hostname, path_prefix.as_deref()))
+    {
+        return Err(anyhow!(
+            "{} is an alias for {}; remove it first with `portless unalias {}`",
+            alias.name(),
+            alias.target(),
+            name
+        ));
+    }
 
     println!("{}", format!("\nportless v{}\n", env!("CARGO_PKG_VERSION")).bold().blue());
     println!("{}", format!("-- {} (auto-resolves to 127.0.0.1)", hostname).dimmed());
@@ -445,75 +1587,248 @@ async fn cmd_run(
         println!("{}", "Proxy started in background".green());
     } else {
         println!("{}", "-- Proxy is running".dimmed());
+        if let Ok(control::Response::Version { version, .. }) =
+            control::send(&state_dir, &control::Request::Version)
+            && version != env!("CARGO_PKG_VERSION")
+        {
+            println!(
+                "{}",
+                format!(
+                    "-- Proxy is v{}, this CLI is v{}; restart it with `portless proxy stop`",
+                    version,
+                    env!("CARGO_PKG_VERSION")
+                )
+                .yellow()
+            );
+        }
     }
 
-    let port = find_free_port()?;
-    println!("{}", format!("-- Using port {}", port).green());
-
-    let store = RouteStore::new(state_dir.clone())?;
+    let app_url = format_url(&hostname, proxy_port, is_https_enabled(&state_dir))
+        + path_prefix.as_deref().unwrap_or("");
     let my_pid = std::process::id();
+    let (port, socket) = if opts.socket {
+        let socket = socket_path(&state_dir, my_pid);
+        if let Some(dir) = socket.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // Left over from a crashed run with the same PID; the app could not bind it.
+        let _ = fs::remove_file(&socket);
+        println!("{}", format!("-- Using socket {}", socket.display()).green());
+        (0, Some(socket))
+    } else {
+        let port = find_free_port()?;
+        println!("{}", format!("-- Using port {}", port).green());
+        (port, None)
+    };
+
+    let probe = opts.ready.clone().unwrap_or(ReadyProbe::Tcp);
+    let startup_timeout = opts.startup_timeout.unwrap_or(startup::STARTUP_TIMEOUT);
 
-    store.add(Route {
+    let mut route = Route {
         hostname: hostname.clone(),
         port,
         pid: my_pid,
-    })?;
-
-    // Inject --port / --host flags for frameworks that ignore the PORT env var
-    inject_framework_flags(&mut cmd, port);
-
-    println!("{}", format!("\n  -> {}\n", app_url).cyan().bold());
-    println!(
-        "{}",
-        format!("Running: PORT={} HOST=127.0.0.1 {}\n", port, cmd.join(" ")).dimmed()
-    );
+        h2c: opts.h2c,
+        command: Some(cmd.join(" ")),
+        started_at: Some(unix_now()),
+        startup_timeout_secs: opts.startup_timeout.map(|d| d.as_secs()),
+        state: RouteState::Starting,
+        health_check: opts.health_check.clone(),
+        path_prefix: path_prefix.clone(),
+        strip_prefix: opts.strip_prefix,
+        join: opts.join,
+        balance: opts.balance,
+        alias: false,
+        upstream: None,
+        socket: socket.clone(),
+        args,
+        cwd: env::current_dir().ok(),
+    };
+    register_route(&store, &state_dir, route.clone())?;
+
+    let mut command = match &socket {
+        Some(socket) => {
+            println!(
+                "{}",
+                format!("Running: PORTLESS_SOCKET={} {}\n", socket.display(), cmd.join(" "))
+                    .dimmed()
+            );
+            let mut command = TokioCommand::new(&cmd[0]);
+            command.envs(opts.env.iter().cloned()).env("PORTLESS_SOCKET", socket);
+            command
+        }
+        None => {
+            // Inject --port / --host flags for frameworks that ignore the PORT env var
+            inject_framework_flags(&mut cmd, port, opts.framework.as_deref());
+            println!(
+                "{}",
+                format!("Running: PORT={} HOST=127.0.0.1 {}\n", port, cmd.join(" ")).dimmed()
+            );
+            let mut command = TokioCommand::new(&cmd[0]);
+            command
+                .envs(opts.env.iter().cloned())
+                .env("PORT", port.to_string())
+                .env("HOST", "127.0.0.1");
+            command
+        }
+    };
 
     let program = cmd[0].clone();
-    let args = &cmd[1..];
-
-    let mut child = TokioCommand::new(&program)
-        .args(args)
-        .env("PORT", port.to_string())
-        .env("HOST", "127.0.0.1")
-        .env("__VITE_ADDITIONAL_SERVER_ALLOWED_HOSTS", ".localhost")
-        .spawn()
-        .map_err(|e| {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                anyhow!(
-                    "Failed to run command: {}\nIs \"{}\" installed and in your PATH?",
-                    e,
-                    program
-                )
-            } else {
-                anyhow!("Failed to spawn '{}': {}", program, e)
-            }
-        })?;
-
-    let child_pid = child.id().unwrap_or(0);
+    command
+        .args(&cmd[1..])
+        .env("__VITE_ADDITIONAL_SERVER_ALLOWED_HOSTS", ".localhost");
+    // The app's output is piped through us, for its log file, prefixes and log
+    // probes; tell it that it may still use colours.
+    command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let has_env = |key: &str| env::var_os(key).is_some() || opts.env.iter().any(|(k, _)| k == key);
+    if std::io::stdout().is_terminal() && !has_env("FORCE_COLOR") && !has_env("NO_COLOR") {
+        command.env("FORCE_COLOR", "1");
+    }
+    let prefix_width = opts.prefix.then(|| opts.prefix_width.unwrap_or(name.len()));
+    let output = AppOutput::new(&name, prefix_width);
+    let log_path = output::app_log_path(&state_dir, &hostname, path_prefix.as_deref());
+    let output = Arc::new(output.with_log_file(log_path).unwrap_or_else(|e| {
+        eprintln!("{}", format!("Warning: cannot keep a log of the app: {}", e).yellow());
+        AppOutput::new(&name, prefix_width)
+    }));
 
     let mut sigint = signal(SignalKind::interrupt())?;
     let mut sigterm = signal(SignalKind::terminate())?;
+    let clean_up = || {
+        deregister_route(&store, &state_dir, &hostname, path_prefix.as_deref(), my_pid);
+        if let Some(socket) = &socket {
+            let _ = fs::remove_file(socket);
+        }
+        shutdown_proxy_if_idle(&store, &state_dir);
+    };
 
-    let exit_status = tokio::select! {
-        status = child.wait() => {
-            status.ok()
+    let mut backoff = supervisor::Backoff::default();
+    let mut restarts = 0;
+    let exit_status = loop {
+        let started = std::time::Instant::now();
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                clean_up();
+                return Err(if e.kind() == std::io::ErrorKind::NotFound {
+                    anyhow!(
+                        "Failed to run command: {}\nIs \"{}\" installed and in your PATH?",
+                        e,
+                        program
+                    )
+                } else {
+                    anyhow!("Failed to spawn '{}': {}", program, e)
+                });
+            }
+        };
+        let child_pid = child.id().unwrap_or(0);
+
+        let log_matched = Arc::new(Notify::new());
+        let mut forwarding = JoinSet::new();
+        if let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) {
+            let pattern = probe.pattern().cloned();
+            forwarding.spawn(ready::forward_output(
+                stdout,
+                false,
+                output.clone(),
+                pattern.clone(),
+                log_matched.clone(),
+            ));
+            forwarding.spawn(ready::forward_output(
+                stderr,
+                true,
+                output.clone(),
+                pattern,
+                log_matched.clone(),
+            ));
+        }
+        let readiness = tokio::spawn(announce_when_ready(
+            route.clone(),
+            state_dir.clone(),
+            probe.clone(),
+            startup_timeout,
+            log_matched,
+            app_url.clone(),
+            opts.open && restarts == 0,
+        ));
+
+        let (exit_status, stopped_by) = tokio::select! {
+            status = child.wait() => (status.ok(), None),
+            _ = sigint.recv() => (None, Some(nix::sys::signal::Signal::SIGINT)),
+            _ = sigterm.recv() => (None, Some(nix::sys::signal::Signal::SIGTERM)),
+        };
+        readiness.abort();
+        if let Some(sig) = stopped_by {
+            forward_signal(child_pid, sig);
+            // The app's output goes through us: stay until it has shut down, or
+            // it may die writing its last lines. A second signal cuts this short.
+            tokio::select! {
+                _ = child.wait() => {}
+                _ = sigint.recv() => {}
+                _ = sigterm.recv() => {}
+                _ = tokio::time::sleep(STOP_TIMEOUT) => {}
+            }
         }
-        _ = sigint.recv() => {
-            forward_signal(child_pid, nix::sys::signal::Signal::SIGINT);
-            let _ = store.remove(&hostname);
-            shutdown_proxy_if_idle(&store, &state_dir);
-            std::process::exit(signal_exit_code(nix::sys::signal::Signal::SIGINT));
+        // Anything the app still runs in the background may hold its output open.
+        let _ = tokio::time::timeout(OUTPUT_DRAIN_TIMEOUT, forwarding.join_all()).await;
+
+        if let Some(sig) = stopped_by {
+            clean_up();
+            std::process::exit(signal_exit_code(sig));
         }
-        _ = sigterm.recv() => {
-            forward_signal(child_pid, nix::sys::signal::Signal::SIGTERM);
-            let _ = store.remove(&hostname);
-            shutdown_proxy_if_idle(&store, &state_dir);
-            std::process::exit(signal_exit_code(nix::sys::signal::Signal::SIGTERM));
+        let Some(status) = exit_status else {
+            break None;
+        };
+        if !opts.restart.is_some_and(|policy| policy.applies(&status)) {
+            break Some(status);
         }
-    };
+        let Some(delay) = backoff.next(started.elapsed()) else {
+            eprintln!(
+                "{}",
+                format!(
+                    "\n-- {} {} and keeps crashing; not restarting it again",
+                    name,
+                    supervisor::describe_exit(&status)
+                )
+                .red()
+            );
+            break Some(status);
+        };
+        eprintln!(
+            "{}",
+            format!(
+                "\n-- {} {}; restarting in {}s",
+                name,
+                supervisor::describe_exit(&status),
+                delay.as_secs()
+            )
+            .yellow()
+        );
 
-    let _ = store.remove(&hostname);
-    shutdown_proxy_if_idle(&store, &state_dir);
+        // Keep the route, so the name and port stay reserved and the proxy
+        // holds requests until the app is back.
+        route.state = RouteState::Starting;
+        route.started_at = Some(unix_now());
+        let _ = register_route(&store, &state_dir, route.clone());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = sigint.recv() => {
+                clean_up();
+                std::process::exit(signal_exit_code(nix::sys::signal::Signal::SIGINT));
+            }
+            _ = sigterm.recv() => {
+                clean_up();
+                std::process::exit(signal_exit_code(nix::sys::signal::Signal::SIGTERM));
+            }
+        }
+        if let Some(socket) = &socket {
+            let _ = fs::remove_file(socket);
+        }
+        route.started_at = Some(unix_now());
+        let _ = register_route(&store, &state_dir, route.clone());
+        restarts += 1;
+    };
+    clean_up();
 
     if let Some(status) = exit_status {
         let code = status.code().unwrap_or(1);
@@ -525,6 +1840,85 @@ async fn cmd_run(
     Ok(())
 }
 
+/// Wait for the app to pass its readiness probe, record the outcome in its
+/// route, and only then print its URL.
+async fn announce_when_ready(
+    mut route: Route,
+    state_dir: PathBuf,
+    probe: ReadyProbe,
+    timeout: Duration,
+    log_matched: Arc<Notify>,
+    app_url: String,
+    open: bool,
+) {
+    let backend = BackendKey::for_route(&route);
+    let ready = timeout.is_zero()
+        || tokio::time::timeout(timeout, probe.wait(&backend, &log_matched))
+            .await
+            .is_ok();
+    route.state = if ready {
+        RouteState::Ready
+    } else {
+        RouteState::Unhealthy
+    };
+    let registered = tokio::task::spawn_blocking(move || {
+        let store = RouteStore::new(state_dir.clone())?;
+        register_route(&store, &state_dir, route)
+    })
+    .await;
+    if !matches!(registered, Ok(Ok(()))) {
+        eprintln!("{}", "Warning: could not update the route state".yellow());
+    }
+
+    if !ready {
+        eprintln!(
+            "{}",
+            format!(
+                "\n-- Not ready after {}s (waiting until {}); requests may fail",
+                timeout.as_secs(),
+                probe.describe()
+            )
+            .yellow()
+        );
+    }
+    println!("{}", format!("\n  -> {}\n", app_url).cyan().bold());
+    if ready
+        && open
+        && let Err(e) = ready::open_browser(&app_url)
+    {
+        eprintln!("{}", format!("Warning: {}", e).yellow());
+    }
+}
+
+/// Register a route through the proxy's control socket, falling back to routes.json.
+fn register_route(store: &RouteStore, state_dir: &std::path::Path, route: Route) -> Result<()> {
+    let request = control::Request::Register {
+        route: route.clone(),
+    };
+    if control::send_ok(state_dir, &request).is_ok() {
+        return Ok(());
+    }
+    store.add(route)
+}
+
+/// Remove the route `pid` registered for `hostname` at `path_prefix`.
+fn deregister_route(
+    store: &RouteStore,
+    state_dir: &std::path::Path,
+    hostname: &str,
+    path_prefix: Option<&str>,
+    pid: u32,
+) {
+    let request = control::Request::Remove {
+        hostname: hostname.to_string(),
+        path_prefix: path_prefix.map(str::to_string),
+        pid: Some(pid),
+    };
+    if control::send_ok(state_dir, &request).is_err() {
+        let _ = store.remove(hostname, path_prefix, Some(pid));
+    }
+}
+
 /// Stop the background proxy if no routes remain after an app exits.
 fn shutdown_proxy_if_idle(store: &RouteStore, state_dir: &std::path::Path) {
     let remaining = store.load(true).unwrap_or_default();
@@ -532,6 +1926,11 @@ fn shutdown_proxy_if_idle(store: &RouteStore, state_dir: &std::path::Path) {
         return;
     }
 
+    if control::send_ok(state_dir, &control::Request::Shutdown).is_ok() {
+        println!("{}", "Proxy stopped (no active routes).".dimmed());
+        return;
+    }
+
     let pid_path = state_dir.join("proxy.pid");
     let port_path = state_dir.join("proxy.port");
 
@@ -550,6 +1949,7 @@ fn shutdown_proxy_if_idle(store: &RouteStore, state_dir: &std::path::Path) {
     {
         let _ = fs::remove_file(&pid_path);
         let _ = fs::remove_file(&port_path);
+        let _ = fs::remove_file(https_marker_path(state_dir));
         println!("{}", "Proxy stopped (no active routes).".dimmed());
     }
 }
@@ -596,36 +1996,46 @@ struct FrameworkFlags {
     strict_port: bool,
 }
 
-fn inject_framework_flags(cmd: &mut Vec<String>, port: u16) {
-    let frameworks: &[(&str, FrameworkFlags)] = &[
-        ("vite", FrameworkFlags { strict_port: true }),
-        ("react-router", FrameworkFlags { strict_port: true }),
-        ("astro", FrameworkFlags { strict_port: false }),
-        ("ng", FrameworkFlags { strict_port: false }),
-    ];
+const FRAMEWORKS: &[(&str, FrameworkFlags)] = &[
+    ("vite", FrameworkFlags { strict_port: true }),
+    ("react-router", FrameworkFlags { strict_port: true }),
+    ("astro", FrameworkFlags { strict_port: false }),
+    ("ng", FrameworkFlags { strict_port: false }),
+];
 
+/// Add the flags of the framework `cmd` runs, or of `framework` if given.
+fn inject_framework_flags(cmd: &mut Vec<String>, port: u16, framework: Option<&str>) {
     let Some(program) = cmd.first() else { return };
     let basename = std::path::Path::new(program)
         .file_name()
         .and_then(|n| n.to_str())
         .unwrap_or(program.as_str());
+    let name = framework.unwrap_or(basename);
 
-    let Some(flags) = frameworks.iter().find(|(name, _)| *name == basename).map(|(_, f)| f) else {
+    let Some(flags) = FRAMEWORKS.iter().find(|(n, _)| *n == name).map(|(_, f)| f) else {
         return;
     };
+    // npm keeps arguments before `--` for itself instead of passing them to the script
+    let needs_separator = basename == "npm" && !cmd.contains(&"--".to_string());
 
+    let mut extra = Vec::new();
     if !cmd.contains(&"--port".to_string()) {
-        cmd.push("--port".to_string());
-        cmd.push(port.to_string());
+        extra.push("--port".to_string());
+        extra.push(port.to_string());
         if flags.strict_port {
-            cmd.push("--strictPort".to_string());
+            extra.push("--strictPort".to_string());
         }
     }
 
     if !cmd.contains(&"--host".to_string()) {
-        cmd.push("--host".to_string());
-        cmd.push("127.0.0.1".to_string());
+        extra.push("--host".to_string());
+        extra.push("127.0.0.1".to_string());
     }
+
+    if needs_separator && !extra.is_empty() {
+        cmd.push("--".to_string());
+    }
+    cmd.extend(extra);
 }
 
 fn prompt(question: &str) -> String {
@@ -637,8 +2047,37 @@ fn prompt(question: &str) -> String {
     line.trim().to_string()
 }
 
+/// Treat `1`, `true` and `yes` (any case) as an enabled boolean env var.
+fn env_flag(name: &str) -> bool {
+    env::var(name)
+        .map(|v| matches!(v.to_ascii_lowercase().as_str(), "1" | "true" | "yes"))
+        .unwrap_or(false)
+}
+
 fn atty_check() -> bool {
     // Check if stdin is a TTY
     use std::os::unix::io::AsRawFd;
     unsafe { nix::libc::isatty(std::io::stdin().as_raw_fd()) == 1 }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn test_without_prefix_options() {
+        let plain = args("--prefix --prefix-width=8 myapp node server.js");
+        assert_eq!(without_prefix_options(&plain).unwrap(), args("myapp node server.js"));
+
+        // Options after the name, a global option, and a command with its own --prefix
+        let after_name = args("myapp --prefix --prefix-width 8 -p 1355 npx tsc --prefix src");
+        assert_eq!(
+            without_prefix_options(&after_name).unwrap(),
+            args("myapp -p 1355 npx tsc --prefix src")
+        );
+    }
+}
@@ -0,0 +1,262 @@
+use hyper::StatusCode;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Status classes counted separately: 1xx to 5xx.
+const STATUS_CLASSES: [&str; 5] = ["1xx", "2xx", "3xx", "4xx", "5xx"];
+
+#[derive(Debug, Default)]
+struct HostMetrics {
+    by_class: [u64; 5],
+    /// Non-cumulative counts per bucket; the last slot is `+Inf`.
+    latency: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+    connect_failures: u64,
+}
+
+/// Gauges tracked with a [`GaugeGuard`].
+#[derive(Debug, Clone, Copy)]
+pub enum Gauge {
+    /// Open client connections.
+    Connections,
+    /// WebSocket tunnels being relayed.
+    WebSockets,
+}
+
+/// Cumulative proxy metrics, rendered in the Prometheus text format.
+/// Unlike [`crate::stats::Stats`], counters of removed routes are kept so they
+/// never go backwards.
+pub struct Metrics {
+    hosts: Mutex<BTreeMap<String, HostMetrics>>,
+    connections: AtomicU64,
+    websockets: AtomicU64,
+}
+
+/// Keeps a gauge incremented until dropped.
+pub struct GaugeGuard {
+    metrics: Arc<Metrics>,
+    gauge: Gauge,
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.metrics.gauge(self.gauge).fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            hosts: Mutex::new(BTreeMap::new()),
+            connections: AtomicU64::new(0),
+            websockets: AtomicU64::new(0),
+        }
+    }
+
+    /// Count a response and how long the backend took to produce its headers.
+    pub fn record(&self, hostname: &str, status: StatusCode, elapsed: Duration) {
+        let class = (status.as_u16() / 100).clamp(1, 5) as usize - 1;
+        let secs = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|le| secs <= *le)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        if let Ok(mut hosts) = self.hosts.lock() {
+            let host = hosts.entry(hostname.to_string()).or_default();
+            host.by_class[class] += 1;
+            host.latency[bucket] += 1;
+            host.latency_sum += secs;
+        }
+    }
+
+    /// Count a request whose backend could not be connected to.
+    pub fn record_connect_failure(&self, hostname: &str) {
+        if let Ok(mut hosts) = self.hosts.lock() {
+            hosts.entry(hostname.to_string()).or_default().connect_failures += 1;
+        }
+    }
+
+    pub fn track(self: &Arc<Self>, gauge: Gauge) -> GaugeGuard {
+        self.gauge(gauge).fetch_add(1, Ordering::Relaxed);
+        GaugeGuard {
+            metrics: self.clone(),
+            gauge,
+        }
+    }
+
+    fn gauge(&self, gauge: Gauge) -> &AtomicU64 {
+        match gauge {
+            Gauge::Connections => &self.connections,
+            Gauge::WebSockets => &self.websockets,
+        }
+    }
+
+    /// Render every metric. Route counts and reloads are owned by the route
+    /// cache and passed in.
+    pub fn render(&self, routes: usize, route_reloads: u64) -> String {
+        let mut out = String::new();
+        let Ok(hosts) = self.hosts.lock() else {
+            return out;
+        };
+
+        header(
+            &mut out,
+            "portless_requests_total",
+            "counter",
+            "Requests proxied, by route and status class.",
+        );
+        for (host, m) in hosts.iter() {
+            for (class, count) in STATUS_CLASSES.iter().zip(m.by_class) {
+                let _ = writeln!(
+                    out,
+                    "portless_requests_total{{host=\"{}\",status=\"{}\"}} {}",
+                    escape_label(host),
+                    class,
+                    count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "portless_request_duration_seconds",
+            "histogram",
+            "Time until the backend's response headers arrived.",
+        );
+        for (host, m) in hosts.iter() {
+            let host = escape_label(host);
+            let mut cumulative = 0;
+            for (le, count) in LATENCY_BUCKETS.iter().zip(m.latency) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "portless_request_duration_seconds_bucket{{host=\"{}\",le=\"{}\"}} {}",
+                    host, le, cumulative
+                );
+            }
+            let total: u64 = m.latency.iter().sum();
+            let _ = writeln!(
+                out,
+                "portless_request_duration_seconds_bucket{{host=\"{}\",le=\"+Inf\"}} {}",
+                host, total
+            );
+            let _ = writeln!(
+                out,
+                "portless_request_duration_seconds_sum{{host=\"{}\"}} {}",
+                host, m.latency_sum
+            );
+            let _ = writeln!(
+                out,
+                "portless_request_duration_seconds_count{{host=\"{}\"}} {}",
+                host, total
+            );
+        }
+
+        header(
+            &mut out,
+            "portless_backend_connect_failures_total",
+            "counter",
+            "Requests whose backend refused or did not accept the connection.",
+        );
+        for (host, m) in hosts.iter() {
+            let _ = writeln!(
+                out,
+                "portless_backend_connect_failures_total{{host=\"{}\"}} {}",
+                escape_label(host),
+                m.connect_failures
+            );
+        }
+
+        let gauges = [
+            (
+                "portless_active_connections",
+                "gauge",
+                "Open client connections.",
+                self.connections.load(Ordering::Relaxed),
+            ),
+            (
+                "portless_active_websocket_tunnels",
+                "gauge",
+                "WebSocket connections being relayed.",
+                self.websockets.load(Ordering::Relaxed),
+            ),
+            ("portless_routes", "gauge", "Registered routes.", routes as u64),
+            (
+                "portless_route_reloads_total",
+                "counter",
+                "Times the route table was reloaded.",
+                route_reloads,
+            ),
+        ];
+        for (name, kind, help, value) in gauges {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{} {}", name, value);
+        }
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_by_status_class_and_latency() {
+        let metrics = Metrics::new();
+        metrics.record("app.localhost", StatusCode::OK, Duration::from_millis(3));
+        metrics.record("app.localhost", StatusCode::NOT_FOUND, Duration::from_millis(30));
+        metrics.record("app.localhost", StatusCode::BAD_GATEWAY, Duration::from_secs(20));
+        metrics.record_connect_failure("app.localhost");
+
+        let text = metrics.render(1, 4);
+        for line in [
+            "portless_requests_total{host=\"app.localhost\",status=\"2xx\"} 1",
+            "portless_requests_total{host=\"app.localhost\",status=\"4xx\"} 1",
+            "portless_requests_total{host=\"app.localhost\",status=\"5xx\"} 1",
+            "portless_requests_total{host=\"app.localhost\",status=\"1xx\"} 0",
+            "portless_request_duration_seconds_bucket{host=\"app.localhost\",le=\"0.005\"} 1",
+            "portless_request_duration_seconds_bucket{host=\"app.localhost\",le=\"0.05\"} 2",
+            "portless_request_duration_seconds_bucket{host=\"app.localhost\",le=\"10\"} 2",
+            "portless_request_duration_seconds_bucket{host=\"app.localhost\",le=\"+Inf\"} 3",
+            "portless_request_duration_seconds_count{host=\"app.localhost\"} 3",
+            "portless_backend_connect_failures_total{host=\"app.localhost\"} 1",
+            "portless_routes 1",
+            "portless_route_reloads_total 4",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {:?} in\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn test_gauges_follow_guards() {
+        let metrics = Arc::new(Metrics::new());
+        let first = metrics.track(Gauge::Connections);
+        let _second = metrics.track(Gauge::Connections);
+        let _tunnel = metrics.track(Gauge::WebSockets);
+        drop(first);
+
+        let text = metrics.render(0, 0);
+        assert!(text.contains("\nportless_active_connections 1\n"), "{}", text);
+        assert!(text.contains("\nportless_active_websocket_tunnels 1\n"), "{}", text);
+    }
+}
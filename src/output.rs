@@ -0,0 +1,265 @@
+use colored::{Color, Colorize};
+use regex::Regex;
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::logfile::{numbered, RotatingFile, LOG_KEEP, LOG_MAX_BYTES};
+use crate::utils::{format_clock, format_rfc3339};
+
+/// Directory in the state dir holding each app's output.
+pub const APP_LOG_DIR: &str = "logs";
+
+/// Colours given to app names, picked by a hash of the name.
+const PALETTE: [Color; 10] = [
+    Color::Cyan,
+    Color::Yellow,
+    Color::Green,
+    Color::Magenta,
+    Color::Blue,
+    Color::BrightCyan,
+    Color::BrightYellow,
+    Color::BrightGreen,
+    Color::BrightMagenta,
+    Color::BrightBlue,
+];
+
+/// Terminal colour and cursor sequences.
+static ANSI_ESCAPE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").expect("valid regex"));
+
+/// `text` without terminal colour and cursor sequences.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    ANSI_ESCAPE.replace_all(text, "")
+}
+
+/// Log file of the app mounted at `hostname` and `path_prefix`, e.g.
+/// `logs/myapp.localhost_api.log` for `myapp/api`.
+pub fn app_log_path(state_dir: &Path, hostname: &str, path_prefix: Option<&str>) -> PathBuf {
+    let mut name = hostname.to_string();
+    if let Some(prefix) = path_prefix {
+        name.push_str(&prefix.replace('/', "_"));
+    }
+    state_dir.join(APP_LOG_DIR).join(format!("{}.log", name))
+}
+
+/// Colour of `name` in prefixed output; the same name always gets the same one.
+pub fn name_colour(name: &str) -> Color {
+    // FNV-1a, so the colour doesn't change between runs or builds.
+    let hash = name
+        .bytes()
+        .fold(0xcbf29ce484222325u64, |h, b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+    PALETTE[(hash % PALETTE.len() as u64) as usize]
+}
+
+/// Where an app's output goes: our stdout/stderr, optionally with each line
+/// prefixed by the time and the app's name, and the app's log file.
+pub struct AppOutput {
+    /// Padded name shown before each line, if prefixing.
+    prefix: Option<String>,
+    colour: Color,
+    log: Option<Mutex<RotatingFile>>,
+}
+
+impl AppOutput {
+    pub fn new(name: &str, prefix_width: Option<usize>) -> Self {
+        Self {
+            prefix: prefix_width.map(|width| format!("{:<width$}", name, width = width)),
+            colour: name_colour(name),
+            log: None,
+        }
+    }
+
+    /// Also append each line, with a timestamp and without colours, to `path`.
+    pub fn with_log_file(mut self, path: PathBuf) -> io::Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        self.log = Some(Mutex::new(RotatingFile::open(path, LOG_MAX_BYTES)?));
+        Ok(self)
+    }
+
+    /// How `line`, read from the app with its line ending, is shown at `unix_ms`.
+    pub fn display<'a>(&self, line: &'a [u8], unix_ms: u64) -> Cow<'a, [u8]> {
+        let Some(prefix) = &self.prefix else {
+            return Cow::Borrowed(line);
+        };
+        let text = String::from_utf8_lossy(line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        let clock = format_clock(unix_ms);
+        let mut shown = format!(
+            "{} {} ",
+            clock[..8].dimmed(),
+            format!("{} |", prefix).color(self.colour)
+        );
+        shown.push_str(text);
+        // Don't let the app's colours run into the next line's prefix.
+        if text.contains('\x1b') {
+            shown.push_str("\x1b[0m");
+        }
+        shown.push('\n');
+        Cow::Owned(shown.into_bytes())
+    }
+
+    /// Append `text`, a line of output without its line ending, to the log file.
+    pub fn log(&self, text: &str, unix_ms: u64) {
+        let Some(log) = &self.log else {
+            return;
+        };
+        let line = format!("{} {}\n", format_rfc3339(unix_ms), strip_ansi(text));
+        if let Ok(mut file) = log.lock()
+            && let Err(e) = file.write_line(&line)
+        {
+            eprintln!("portless: cannot write app log: {}", e);
+        }
+    }
+}
+
+/// Split a line of an app's log file into its time (Unix ms) and the app's text.
+pub fn parse_log_line(line: &str) -> Option<(u64, &str)> {
+    let (stamp, text) = line.split_once(' ')?;
+    let time = OffsetDateTime::parse(stamp, &Rfc3339).ok()?;
+    Some(((time.unix_timestamp_nanos() / 1_000_000) as u64, text))
+}
+
+/// Rotated copies of the log file at `path` that exist, oldest first.
+pub fn rotated_logs(path: &Path) -> Vec<PathBuf> {
+    (1..=LOG_KEEP)
+        .rev()
+        .map(|n| numbered(path, n))
+        .filter(|p| p.is_file())
+        .collect()
+}
+
+/// Reads the lines appended to a log file, moving on to the new file when it
+/// is rotated.
+pub struct LogReader {
+    path: PathBuf,
+    file: Option<BufReader<File>>,
+    ino: Option<u64>,
+    /// Start of a line whose end has not been written yet.
+    partial: String,
+}
+
+impl LogReader {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            ino: None,
+            partial: String::new(),
+        }
+    }
+
+    /// Lines completed since the last call (every line, on the first), without
+    /// their line endings. A file that does not exist yet has none.
+    pub fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        // Finish the file we have open even if it was rotated away meanwhile.
+        if let Some(file) = &mut self.file {
+            read_complete_lines(file, &mut self.partial, &mut lines)?;
+        }
+        let ino = fs::metadata(&self.path).ok().map(|meta| meta.ino());
+        if ino.is_some() && ino != self.ino {
+            let mut file = BufReader::new(File::open(&self.path)?);
+            self.partial.clear();
+            read_complete_lines(&mut file, &mut self.partial, &mut lines)?;
+            self.file = Some(file);
+            self.ino = ino;
+        }
+        Ok(lines)
+    }
+}
+
+fn read_complete_lines(
+    file: &mut BufReader<File>,
+    partial: &mut String,
+    lines: &mut Vec<String>,
+) -> io::Result<()> {
+    while file.read_line(partial)? > 0 {
+        if partial.ends_with('\n') {
+            let line = std::mem::take(partial);
+            lines.push(line.trim_end_matches(['\r', '\n']).to_string());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_app_log_path() {
+        let dir = Path::new("/state");
+        assert_eq!(
+            app_log_path(dir, "myapp.localhost", None),
+            Path::new("/state/logs/myapp.localhost.log")
+        );
+        assert_eq!(
+            app_log_path(dir, "myapp.localhost", Some("/api/v1")),
+            Path::new("/state/logs/myapp.localhost_api_v1.log")
+        );
+    }
+
+    #[test]
+    fn test_prefixed_lines() {
+        assert_eq!(name_colour("web"), name_colour("web"));
+
+        let raw = AppOutput::new("web", None);
+        assert_eq!(&*raw.display(b"hello\r\n", 0), b"hello\r\n");
+
+        let prefixed = AppOutput::new("web", Some(6));
+        let shown = prefixed.display(b"hello\n", 0);
+        let shown = String::from_utf8_lossy(&shown);
+        let shown = strip_ansi(&shown);
+        assert!(shown.ends_with(" web    | hello\n"), "{:?}", shown);
+        assert_eq!(shown.len(), "HH:MM:SS web    | hello\n".len());
+    }
+
+    #[test]
+    fn test_log_file_strips_colours() {
+        let temp = TempDir::new().unwrap();
+        let path = app_log_path(temp.path(), "web.localhost", None);
+        let output = AppOutput::new("web", None).with_log_file(path.clone()).unwrap();
+        output.log("\x1b[32mready\x1b[0m in 20ms", 1_700_000_000_123);
+
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "2023-11-14T22:13:20.123Z ready in 20ms\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_log_line() {
+        assert_eq!(
+            parse_log_line("2023-11-14T22:13:20.123Z ready in 20ms"),
+            Some((1_700_000_000_123, "ready in 20ms"))
+        );
+        assert_eq!(parse_log_line("2023-11-14T22:13:20Z "), Some((1_700_000_000_000, "")));
+        assert_eq!(parse_log_line("ready in 20ms"), None);
+    }
+
+    #[test]
+    fn test_log_reader_follows_rotation() {
+        let temp = TempDir::new().unwrap();
+        let path = temp.path().join("web.localhost.log");
+        let mut reader = LogReader::new(path.clone());
+        assert!(reader.read_lines().unwrap().is_empty());
+
+        fs::write(&path, "one\ntw").unwrap();
+        assert_eq!(reader.read_lines().unwrap(), ["one"]);
+
+        let mut file = RotatingFile::open(path.clone(), 10).unwrap();
+        file.write_line("o\n").unwrap();
+        file.write_line("three\n").unwrap();
+        assert_eq!(reader.read_lines().unwrap(), ["two", "three"]);
+        assert_eq!(rotated_logs(&path), [numbered(&path, 1)]);
+    }
+}
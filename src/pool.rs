@@ -0,0 +1,655 @@
+use http_body_util::combinators::BoxBody;
+use hyper::body::{Bytes, Incoming};
+use hyper::client::conn::{http1, http2, TrySendError};
+use hyper::{Request, Response};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock, Mutex};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::time::{Duration, Instant};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::ClientConfig;
+use tokio_rustls::TlsConnector;
+
+use crate::tls;
+use crate::types::{Route, Upstream};
+
+pub type ProxyBody = BoxBody<Bytes, hyper::Error>;
+
+/// Idle backend connections are closed after this long without use.
+pub const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Maximum number of idle HTTP/1 connections kept per backend.
+pub const POOL_MAX_IDLE_PER_HOST: usize = 16;
+/// How long connecting to a remote upstream, TLS handshake included, may take.
+pub const UPSTREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TLS settings for `https://` upstreams, loaded on first use.
+static UPSTREAM_TLS: LazyLock<Arc<ClientConfig>> =
+    LazyLock::new(|| tls::client_config(tls::system_roots()));
+
+/// Identifies a backend connection slot: where the backend is and the protocol
+/// spoken to it.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BackendKey {
+    pub port: u16,
+    pub http2: bool,
+    /// Set for routes to a remote origin.
+    pub upstream: Option<Upstream>,
+    /// Set for apps listening on a Unix socket. With neither this nor
+    /// `upstream`, the backend is `port` on localhost.
+    pub socket: Option<PathBuf>,
+}
+
+impl BackendKey {
+    pub fn for_route(route: &Route) -> Self {
+        Self {
+            port: route.port,
+            http2: route.h2c,
+            upstream: route.upstream.clone(),
+            socket: route.socket.clone(),
+        }
+    }
+
+    /// Host header the backend expects: `localhost:<port>` for local apps,
+    /// so dev servers like Vite accept the request, else the upstream's own.
+    pub fn authority(&self) -> String {
+        match (&self.upstream, &self.socket) {
+            (Some(upstream), _) => upstream.authority(),
+            (None, Some(_)) => "localhost".to_string(),
+            (None, None) => format!("localhost:{}", self.port),
+        }
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        self.upstream.as_ref().map_or("http", Upstream::scheme)
+    }
+}
+
+/// A connection to a backend: TCP, a Unix socket, or TLS for an `https://` upstream.
+pub trait BackendIo: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> BackendIo for T {}
+
+pub type BackendStream = Box<dyn BackendIo>;
+
+/// Response extension recording how long opening a new backend connection took.
+/// Absent when the request went over a pooled connection.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectTime(pub Duration);
+
+/// Response extension on the proxy's own 502 when the backend could not be
+/// connected to, as opposed to a 502 sent by the backend itself.
+#[derive(Debug, Clone, Copy)]
+pub struct BackendUnreachable;
+
+pub enum PoolError {
+    /// The backend could not be reached at all.
+    Connect(std::io::Error),
+    /// The connection was established but the exchange failed.
+    Http(hyper::Error),
+}
+
+enum Sender {
+    Http1(http1::SendRequest<ProxyBody>),
+    Http2(http2::SendRequest<ProxyBody>),
+}
+
+impl Sender {
+    fn is_closed(&self) -> bool {
+        match self {
+            Sender::Http1(s) => s.is_closed(),
+            Sender::Http2(s) => s.is_closed(),
+        }
+    }
+}
+
+struct Idle {
+    sender: Sender,
+    since: Instant,
+}
+
+/// Keep-alive connections to backends, shared by every client connection of the proxy.
+///
+/// HTTP/1 connections are checked out exclusively and returned once the response
+/// body has been fully read. An h2c backend gets a single multiplexed connection
+/// that stays in the pool while in use.
+pub struct BackendPool {
+    idle: Mutex<HashMap<BackendKey, Vec<Idle>>>,
+    idle_timeout: Duration,
+    max_idle_per_host: usize,
+}
+
+impl BackendPool {
+    pub fn new(idle_timeout: Duration, max_idle_per_host: usize) -> Self {
+        Self {
+            idle: Mutex::new(HashMap::new()),
+            idle_timeout,
+            max_idle_per_host,
+        }
+    }
+
+    /// Send `req` to the backend, reusing an idle connection when one is available.
+    pub async fn send(
+        self: &Arc<Self>,
+        key: &BackendKey,
+        mut req: Request<ProxyBody>,
+    ) -> Result<Response<Incoming>, PoolError> {
+        // A pooled connection may have been closed by the backend in the meantime.
+        // hyper hands the request back if it was never written, so retrying is safe.
+        while let Some(sender) = self.checkout(key) {
+            match self.dispatch(key, sender, req).await {
+                Ok(resp) => return Ok(resp),
+                Err(mut e) => match e.take_message() {
+                    Some(r) => req = r,
+                    None => return Err(PoolError::Http(e.into_error())),
+                },
+            }
+        }
+
+        let connect_start = Instant::now();
+        let sender = self.connect(key).await?;
+        let connect_time = ConnectTime(connect_start.elapsed());
+        let mut resp = self
+            .dispatch(key, sender, req)
+            .await
+            .map_err(|e| PoolError::Http(e.into_error()))?;
+        resp.extensions_mut().insert(connect_time);
+        Ok(resp)
+    }
+
+    async fn dispatch(
+        self: &Arc<Self>,
+        key: &BackendKey,
+        sender: Sender,
+        req: Request<ProxyBody>,
+    ) -> Result<Response<Incoming>, TrySendError<Request<ProxyBody>>> {
+        match sender {
+            Sender::Http1(mut s) => {
+                let resp = s.try_send_request(req).await?;
+                // The connection is busy until the response body has been consumed;
+                // `ready` resolves at that point, so the sender can go back to the pool.
+                let pool = self.clone();
+                let key = key.clone();
+                tokio::spawn(async move {
+                    if s.ready().await.is_ok() {
+                        pool.checkin(key, Sender::Http1(s));
+                    }
+                });
+                Ok(resp)
+            }
+            Sender::Http2(mut s) => s.try_send_request(req).await,
+        }
+    }
+
+    async fn connect(&self, key: &BackendKey) -> Result<Sender, PoolError> {
+        let stream = connect(key).await.map_err(PoolError::Connect)?;
+        let io = TokioIo::new(stream);
+
+        if key.http2 {
+            let (sender, conn) = http2::handshake(TokioExecutor::new(), io)
+                .await
+                .map_err(PoolError::Http)?;
+            tokio::spawn(conn);
+            self.checkin(key.clone(), Sender::Http2(sender.clone()));
+            Ok(Sender::Http2(sender))
+        } else {
+            let (sender, conn) = http1::handshake(io).await.map_err(PoolError::Http)?;
+            tokio::spawn(conn);
+            Ok(Sender::Http1(sender))
+        }
+    }
+
+    fn checkout(&self, key: &BackendKey) -> Option<Sender> {
+        let mut idle = self.idle.lock().ok()?;
+        let entries = idle.get_mut(key)?;
+        let now = Instant::now();
+        entries.retain(|e| !e.sender.is_closed() && now.duration_since(e.since) < self.idle_timeout);
+
+        if key.http2 {
+            let entry = entries.last_mut()?;
+            entry.since = now;
+            match &entry.sender {
+                Sender::Http2(s) => Some(Sender::Http2(s.clone())),
+                Sender::Http1(_) => None,
+            }
+        } else {
+            entries.pop().map(|e| e.sender)
+        }
+    }
+
+    fn checkin(&self, key: BackendKey, sender: Sender) {
+        let Ok(mut idle) = self.idle.lock() else {
+            return;
+        };
+        let limit = if key.http2 { 1 } else { self.max_idle_per_host };
+        let entries = idle.entry(key).or_default();
+        if entries.len() < limit {
+            entries.push(Idle {
+                sender,
+                since: Instant::now(),
+            });
+        }
+    }
+
+    /// Drop idle connections to backends for which `is_live` returns false,
+    /// e.g. after a route moved to a different port.
+    pub fn retain(&self, is_live: impl Fn(&BackendKey) -> bool) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.retain(|key, _| is_live(key));
+        }
+    }
+
+    /// Close connections that have been idle for longer than the idle timeout.
+    pub fn purge_expired(&self) {
+        let now = Instant::now();
+        if let Ok(mut idle) = self.idle.lock() {
+            for entries in idle.values_mut() {
+                entries.retain(|e| {
+                    !e.sender.is_closed() && now.duration_since(e.since) < self.idle_timeout
+                });
+            }
+            idle.retain(|_, entries| !entries.is_empty());
+        }
+    }
+
+    #[cfg(test)]
+    fn idle_count(&self, key: &BackendKey) -> usize {
+        self.idle
+            .lock()
+            .map(|idle| idle.get(key).map_or(0, Vec::len))
+            .unwrap_or(0)
+    }
+}
+
+/// Open a connection to the backend identified by `key`.
+pub async fn connect(key: &BackendKey) -> std::io::Result<BackendStream> {
+    match (&key.upstream, &key.socket) {
+        (Some(upstream), _) => {
+            connect_upstream(upstream, key.http2, &UPSTREAM_TLS, UPSTREAM_CONNECT_TIMEOUT).await
+        }
+        (None, Some(socket)) => Ok(Box::new(UnixStream::connect(socket).await?)),
+        (None, None) => {
+            let stream = connect_to_backend(key.port).await?;
+            let _ = stream.set_nodelay(true);
+            Ok(Box::new(stream))
+        }
+    }
+}
+
+/// Connect to a remote origin. Over TLS, the upstream's host name is sent as
+/// SNI and its certificate checked against `config`'s roots. Fails with
+/// `TimedOut` if the connection is not ready within `timeout`.
+async fn connect_upstream(
+    upstream: &Upstream,
+    http2: bool,
+    config: &Arc<ClientConfig>,
+    timeout: Duration,
+) -> std::io::Result<BackendStream> {
+    let connect = async {
+        let stream = TcpStream::connect((upstream.host.as_str(), upstream.port)).await?;
+        let _ = stream.set_nodelay(true);
+        if !upstream.tls {
+            return Ok::<BackendStream, std::io::Error>(Box::new(stream));
+        }
+        let server_name = ServerName::try_from(upstream.host.clone())
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        let mut config = ClientConfig::clone(config);
+        config.alpn_protocols = vec![if http2 { b"h2".to_vec() } else { b"http/1.1".to_vec() }];
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(server_name, stream)
+            .await?;
+        Ok(Box::new(stream))
+    };
+    tokio::time::timeout(timeout, connect).await.map_err(|_| {
+        std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            format!("no connection within {}s", timeout.as_secs_f32()),
+        )
+    })?
+}
+
+/// Connect to a local backend port, trying IPv4 first then IPv6.
+/// Node.js 18+ on macOS resolves `localhost` to ::1 (IPv6) by default,
+/// so hardcoding 127.0.0.1 fails when the backend only binds to ::1.
+pub async fn connect_to_backend(port: u16) -> std::io::Result<TcpStream> {
+    if let Ok(s) = TcpStream::connect(("127.0.0.1", port)).await {
+        return Ok(s);
+    }
+    TcpStream::connect(("::1", port)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Empty, Full};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::pki_types::CertificateDer;
+    use tokio_rustls::rustls::RootCertStore;
+    use tokio_rustls::TlsAcceptor;
+
+    /// Start an HTTP/1 server that counts accepted TCP connections.
+    async fn counting_server() -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(|_req| async {
+                        Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from("ok"))))
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        (port, accepted)
+    }
+
+    /// Start an HTTP/2-only (h2c) server that counts accepted TCP connections
+    /// and answers with the protocol version it saw.
+    async fn h2c_server() -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(|req: Request<Incoming>| {
+                        let body = format!("{:?}", req.version());
+                        async move {
+                            Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(body))))
+                        }
+                    });
+                    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        (port, accepted)
+    }
+
+    fn get(port: u16) -> Request<ProxyBody> {
+        Request::builder()
+            .uri("/")
+            .header("host", format!("localhost:{}", port))
+            .body(Empty::<Bytes>::new().map_err(|e| match e {}).boxed())
+            .unwrap()
+    }
+
+    async fn fetch(pool: &Arc<BackendPool>, key: &BackendKey) {
+        let Ok(resp) = pool.send(key, get(key.port)).await else {
+            panic!("request failed");
+        };
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "ok");
+        // Let the check-in task run.
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    #[tokio::test]
+    async fn test_connections_are_reused() {
+        let (port, accepted) = counting_server().await;
+        let pool = Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST));
+        let key = BackendKey {
+            port,
+            ..Default::default()
+        };
+
+        let first = pool.send(&key, get(port)).await.ok().unwrap();
+        assert!(first.extensions().get::<ConnectTime>().is_some());
+        first.into_body().collect().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let second = pool.send(&key, get(port)).await.ok().unwrap();
+        assert!(second.extensions().get::<ConnectTime>().is_none());
+        second.into_body().collect().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        fetch(&pool, &key).await;
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.idle_count(&key), 1);
+    }
+
+    #[tokio::test]
+    async fn test_h2c_backend_shares_one_connection() {
+        let (port, accepted) = h2c_server().await;
+        let pool = Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST));
+        let key = BackendKey {
+            port,
+            http2: true,
+            ..Default::default()
+        };
+        let get_h2 = || {
+            let mut req = get(port);
+            *req.uri_mut() = format!("http://{}/", key.authority()).parse().unwrap();
+            *req.version_mut() = hyper::Version::HTTP_2;
+            req.headers_mut().remove("host");
+            req
+        };
+        let read = |resp: Result<Response<Incoming>, PoolError>| async move {
+            let Ok(resp) = resp else {
+                panic!("request failed");
+            };
+            resp.into_body().collect().await.unwrap().to_bytes()
+        };
+
+        let first = pool.send(&key, get_h2()).await;
+        assert!(first.as_ref().ok().unwrap().extensions().get::<ConnectTime>().is_some());
+        assert_eq!(read(first).await, "HTTP/2.0");
+
+        // Concurrent requests are multiplexed over the shared sender.
+        let (second, third) = tokio::join!(pool.send(&key, get_h2()), pool.send(&key, get_h2()));
+        assert!(second.as_ref().ok().unwrap().extensions().get::<ConnectTime>().is_none());
+        assert_eq!(read(second).await, "HTTP/2.0");
+        assert_eq!(read(third).await, "HTTP/2.0");
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+        assert_eq!(pool.idle_count(&key), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired_connections_are_not_reused() {
+        let (port, accepted) = counting_server().await;
+        let pool = Arc::new(BackendPool::new(Duration::ZERO, POOL_MAX_IDLE_PER_HOST));
+        let key = BackendKey {
+            port,
+            ..Default::default()
+        };
+
+        fetch(&pool, &key).await;
+        fetch(&pool, &key).await;
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retain_evicts_stale_backends() {
+        let (port, _) = counting_server().await;
+        let pool = Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST));
+        let key = BackendKey {
+            port,
+            ..Default::default()
+        };
+
+        fetch(&pool, &key).await;
+        assert_eq!(pool.idle_count(&key), 1);
+
+        pool.retain(|k| k.port != port);
+        assert_eq!(pool.idle_count(&key), 0);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_is_a_separate_backend() {
+        let (port, accepted) = counting_server().await;
+        let pool = Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST));
+        let local = BackendKey {
+            port,
+            ..Default::default()
+        };
+        let upstream = BackendKey {
+            port,
+            upstream: Some(format!("127.0.0.1:{}", port).parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(upstream.authority(), format!("127.0.0.1:{}", port));
+
+        fetch(&pool, &local).await;
+        fetch(&pool, &upstream).await;
+        fetch(&pool, &upstream).await;
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 2);
+        assert_eq!(pool.idle_count(&local), 1);
+        assert_eq!(pool.idle_count(&upstream), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_backend() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let path = temp.path().join("app.sock");
+        let listener = tokio::net::UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(|req: Request<Incoming>| {
+                        let body = format!("{:?}", req.headers().get("host"));
+                        async move {
+                            Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(body))))
+                        }
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+
+        let pool = Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST));
+        let key = BackendKey {
+            socket: Some(path),
+            ..Default::default()
+        };
+        let mut req = get(0);
+        req.headers_mut()
+            .insert("host", key.authority().parse().unwrap());
+        let Ok(resp) = pool.send(&key, req).await else {
+            panic!("request failed");
+        };
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Some(\"localhost\")");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(pool.idle_count(&key), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tls_upstream_verifies_certificate() {
+        let temp = tempfile::TempDir::new().unwrap();
+        let acceptor = TlsAcceptor::from(tls::server_config(temp.path()).unwrap());
+        let ca = tls::LocalCa::load_or_create(temp.path()).unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(ca.cert_der().to_vec())).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(tls) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    // Answer with the SNI name and Host header the proxy sent
+                    let sni = tls.get_ref().1.server_name().unwrap_or("").to_string();
+                    let service = hyper::service::service_fn(move |req: Request<Incoming>| {
+                        let body = format!("{} {:?}", sni, req.headers().get("host"));
+                        async move {
+                            Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(body))))
+                        }
+                    });
+                    let _ = hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(tls), service)
+                        .await;
+                });
+            }
+        });
+
+        let upstream = Upstream {
+            host: "localhost".to_string(),
+            port,
+            tls: true,
+        };
+        let config = tls::client_config(roots);
+        let stream = connect_upstream(&upstream, false, &config, UPSTREAM_CONNECT_TIMEOUT)
+            .await
+            .unwrap();
+        let (mut sender, conn) = http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(conn);
+        let mut req = get(port);
+        req.headers_mut()
+            .insert("host", upstream.authority().parse().unwrap());
+        let resp = sender.send_request(req).await.unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, format!("localhost Some(\"localhost:{}\")", port));
+
+        // Without the CA among its roots the proxy refuses the upstream
+        let untrusted = tls::client_config(RootCertStore::empty());
+        let result = connect_upstream(&upstream, false, &untrusted, UPSTREAM_CONNECT_TIMEOUT);
+        assert!(result.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_handshake_times_out() {
+        // Accepts the connection but never answers the TLS handshake
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut open = Vec::new();
+            loop {
+                open.push(listener.accept().await.unwrap().0);
+            }
+        });
+
+        let upstream = Upstream {
+            host: "localhost".to_string(),
+            port,
+            tls: true,
+        };
+        let config = tls::client_config(RootCertStore::empty());
+        let err = connect_upstream(&upstream, false, &config, Duration::from_millis(100))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[tokio::test]
+    async fn test_connect_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        let pool = Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST));
+        let key = BackendKey {
+            port,
+            ..Default::default()
+        };
+        assert!(matches!(
+            pool.send(&key, get(port)).await,
+            Err(PoolError::Connect(_))
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::utils::{parse_hostname, parse_path_prefix};
+
+/// File `portless up` looks for in the current directory and its parents.
+pub const PROJECT_FILE: &str = "portless.toml";
+
+/// A `portless.toml`, declaring the apps of a dev stack.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Project {
+    #[serde(default, rename = "app")]
+    pub apps: Vec<App>,
+    /// Directory holding the file; app directories are relative to it.
+    #[serde(skip)]
+    pub root: PathBuf,
+}
+
+/// One `[[app]]` table: what `portless <name> <command>` would be given.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct App {
+    /// Name as for `portless <name>`, e.g. `web` for web.localhost.
+    pub name: String,
+    pub command: CommandLine,
+    /// Directory to run the command in, relative to the file.
+    pub cwd: Option<PathBuf>,
+    /// Extra environment variables for the command.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Inject this framework's port flags, for commands like `npm run dev`
+    /// that do not name it.
+    pub framework: Option<String>,
+    /// Readiness probe, as for `--ready`.
+    pub ready: Option<String>,
+    /// Path prefix to mount the app at, e.g. `/api`.
+    pub path: Option<String>,
+    #[serde(default)]
+    pub strip_prefix: bool,
+    pub health_check: Option<String>,
+    pub startup_timeout: Option<String>,
+    /// Restart policy, as for `--restart`.
+    pub restart: Option<String>,
+    #[serde(default)]
+    pub h2c: bool,
+    #[serde(default)]
+    pub socket: bool,
+}
+
+/// A command as one string, split like a shell would (without expanding
+/// anything), or as a list of arguments.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum CommandLine {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl CommandLine {
+    pub fn to_args(&self) -> Result<Vec<String>> {
+        let args = match self {
+            CommandLine::Line(line) => shlex::split(line)
+                .ok_or_else(|| anyhow!("Unbalanced quotes in command: {}", line))?,
+            CommandLine::Args(args) => args.clone(),
+        };
+        if args.is_empty() {
+            return Err(anyhow!("Command is empty"));
+        }
+        Ok(args)
+    }
+}
+
+/// Find the project file in `start` or the nearest parent directory having one.
+pub fn find(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// Read and check the project file at `path`.
+pub fn load(path: &Path) -> Result<Project> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Cannot read {}: {}", path.display(), e))?;
+    let mut project = parse(&text).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+    project.root = path.parent().map(Path::to_path_buf).unwrap_or_default();
+    Ok(project)
+}
+
+fn parse(text: &str) -> Result<Project> {
+    let project: Project = toml::from_str(text)?;
+    if project.apps.is_empty() {
+        return Err(anyhow!("no [[app]] tables"));
+    }
+    let mut seen = HashSet::new();
+    for app in &project.apps {
+        let route = app.route_name();
+        parse_hostname(&route).map_err(|e| anyhow!("app {}: {}", route, e))?;
+        parse_path_prefix(&route).map_err(|e| anyhow!("app {}: {}", route, e))?;
+        app.command
+            .to_args()
+            .map_err(|e| anyhow!("app {}: {}", route, e))?;
+        if !seen.insert(route.clone()) {
+            return Err(anyhow!("app {} is declared twice", route));
+        }
+    }
+    Ok(project)
+}
+
+impl Project {
+    /// The apps called `names`, or all of them if `names` is empty.
+    pub fn select(&self, names: &[String]) -> Result<Vec<&App>> {
+        if names.is_empty() {
+            return Ok(self.apps.iter().collect());
+        }
+        names
+            .iter()
+            .map(|name| {
+                self.apps
+                    .iter()
+                    .find(|app| &app.route_name() == name)
+                    .ok_or_else(|| anyhow!("No app named {} in {}", name, PROJECT_FILE))
+            })
+            .collect()
+    }
+}
+
+impl App {
+    /// `name` plus the path prefix, e.g. `web/api`.
+    pub fn route_name(&self) -> String {
+        match &self.path {
+            Some(path) => format!("{}/{}", self.name, path.trim_start_matches('/')),
+            None => self.name.clone(),
+        }
+    }
+
+    /// Directory the command runs in.
+    pub fn dir(&self, root: &Path) -> PathBuf {
+        match &self.cwd {
+            Some(cwd) => root.join(cwd),
+            None => root.to_path_buf(),
+        }
+    }
+
+    /// Arguments for `portless` that run this app.
+    pub fn run_args(&self) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        let options = [
+            ("--framework", &self.framework),
+            ("--ready", &self.ready),
+            ("--health-check", &self.health_check),
+            ("--startup-timeout", &self.startup_timeout),
+            ("--restart", &self.restart),
+        ];
+        for (flag, value) in options {
+            if let Some(value) = value {
+                args.push(flag.to_string());
+                args.push(value.clone());
+            }
+        }
+        for (key, value) in &self.env {
+            args.push("--env".to_string());
+            args.push(format!("{}={}", key, value));
+        }
+        let switches = [
+            ("--strip-prefix", self.strip_prefix),
+            ("--h2c", self.h2c),
+            ("--socket", self.socket),
+        ];
+        for (flag, on) in switches {
+            if on {
+                args.push(flag.to_string());
+            }
+        }
+        args.push(self.route_name());
+        args.extend(self.command.to_args()?);
+        Ok(args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const STACK: &str = r#"
+[[app]]
+name = "web"
+command = "npm run dev -- --open 'false'"
+cwd = "apps/web"
+framework = "vite"
+restart = "on-failure"
+
+[[app]]
+name = "web"
+path = "/api"
+command = ["cargo", "run", "-p", "api"]
+env = { RUST_LOG = "debug" }
+ready = "http:/healthz"
+strip-prefix = true
+"#;
+
+    #[test]
+    fn test_parse_stack() {
+        let project = parse(STACK).unwrap();
+        let [web, api] = &project.apps[..] else {
+            panic!("expected two apps");
+        };
+        assert_eq!(
+            web.run_args().unwrap(),
+            [
+                "--framework", "vite", "--restart", "on-failure", "web", "npm", "run", "dev", "--",
+                "--open", "false"
+            ]
+        );
+        assert_eq!(api.route_name(), "web/api");
+        assert_eq!(api.env["RUST_LOG"], "debug");
+        assert_eq!(
+            api.run_args().unwrap(),
+            [
+                "--ready", "http:/healthz", "--env", "RUST_LOG=debug", "--strip-prefix", "web/api",
+                "cargo", "run", "-p", "api"
+            ]
+        );
+        assert_eq!(web.dir(Path::new("/repo")), Path::new("/repo/apps/web"));
+        assert_eq!(api.dir(Path::new("/repo")), Path::new("/repo"));
+
+        assert_eq!(project.select(&[]).unwrap().len(), 2);
+        let selected = project.select(&["web/api".to_string()]).unwrap();
+        assert_eq!(selected[0].path.as_deref(), Some("/api"));
+        assert!(project.select(&["docs".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_stacks() {
+        let duplicate = "[[app]]\nname = \"web\"\ncommand = \"a\"\n\
+                         [[app]]\nname = \"web\"\ncommand = \"b\"\n";
+        assert!(parse(duplicate).unwrap_err().to_string().contains("declared twice"));
+        assert!(parse("[[app]]\nname = \"web\"\ncommand = \"\"\n").is_err());
+        assert!(parse("[[app]]\nname = \"web\"\ncommand = \"echo 'hi\"\n").is_err());
+        assert!(parse("[[app]]\nname = \"web\"\ncommand = \"a\"\nport = 3000\n").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn test_find_in_parent_directory() {
+        let temp = TempDir::new().unwrap();
+        let nested = temp.path().join("apps/web/src");
+        std::fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find(&nested), None);
+
+        std::fs::write(temp.path().join(PROJECT_FILE), STACK).unwrap();
+        let found = find(&nested).unwrap();
+        assert_eq!(found, temp.path().join(PROJECT_FILE));
+        assert_eq!(load(&found).unwrap().root, temp.path());
+    }
+}
@@ -1,75 +1,202 @@
 use anyhow::Result;
 use http_body_util::{combinators::BoxBody, BodyExt, Empty, Full};
-use hyper::body::Bytes;
+use hyper::body::{Body, Bytes};
 use hyper::header::{HeaderValue, UPGRADE};
-use hyper::{Request, Response, StatusCode};
-use hyper_util::rt::TokioIo;
+use hyper::{Request, Response, StatusCode, Uri, Version};
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
-use tokio::io::AsyncReadExt;
-use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::watch;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{
+    AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, Lines,
+};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{broadcast, watch};
 use tokio::time::{Duration, Instant};
+use tokio_rustls::TlsAcceptor;
 
-use crate::routes::RouteStore;
-use crate::types::Route;
-use crate::utils::escape_html;
+use crate::access_log::{AccessEntry, AccessLog, AccessLogFormat};
+use crate::balance::{Balancer, InFlight};
+use crate::control::{
+    self, ProxyStatus, Request as ControlRequest, Response as ControlResponse,
+};
+use crate::dashboard;
+use crate::health::{HealthMonitor, HEALTH_CHECK_INTERVAL};
+use crate::logfile;
+use crate::metrics::{Gauge, Metrics};
+use crate::capture::{CaptureLog, CAPTURE_CAPACITY};
+use crate::pool::{
+    self, BackendKey, BackendPool, BackendUnreachable, PoolError, ProxyBody,
+    POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST,
+};
+use crate::routes::{find_route, instances, is_pid_alive, RouteStore};
+use crate::startup::{self, StartupTracker};
+use crate::types::{Route, RouteState};
+use crate::stats::Stats;
+use crate::utils::{
+    escape_html, hostname_matches, https_marker_path, wildcard_label, DASHBOARD_HOSTNAME,
+};
+use crate::watcher::{spawn_route_watcher, RouteCache};
 
 /// After all routes disappear, wait this long before shutting down.
 const IDLE_SHUTDOWN_DELAY: Duration = Duration::from_secs(5);
 /// Grace period after startup before idle-shutdown is armed (lets the first app register).
 const IDLE_GRACE: Duration = Duration::from_secs(10);
-/// How often the route-reloader re-reads routes.json.
-const ROUTE_RELOAD_INTERVAL: Duration = Duration::from_millis(100);
+/// How often registered app PIDs are probed for the idle-shutdown signal.
+const LIVENESS_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+/// First byte of a TLS record carrying a handshake (ClientHello).
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+/// How often idle backend connections are checked for expiry.
+const POOL_REAP_INTERVAL: Duration = Duration::from_secs(5);
+/// How often `proxy.log` is checked against its size limit.
+const LOG_ROTATE_INTERVAL: Duration = Duration::from_secs(60);
+/// Request header carrying the subdomain labels a wildcard route matched.
+const TENANT_HEADER: &str = "x-portless-tenant";
 
-pub async fn run_proxy(port: u16, state_dir: PathBuf) -> Result<()> {
+pub struct ProxyConfig {
+    pub port: u16,
+    pub state_dir: PathBuf,
+    /// Terminate TLS with certificates minted from the local CA.
+    pub https: bool,
+    /// Format of `access.log` in the state dir.
+    pub access_log: AccessLogFormat,
+}
+
+/// State shared by every client connection.
+struct ProxyState {
+    routes: Arc<RouteCache>,
+    pool: Arc<BackendPool>,
+    proxy_port: u16,
+    https: bool,
+    state_dir: PathBuf,
+    started_at: Instant,
+    stats: Arc<Stats>,
+    captures: Arc<CaptureLog>,
+    access_log: Option<Arc<AccessLog>>,
+    metrics: Arc<Metrics>,
+    startup: Arc<StartupTracker>,
+    health: Arc<HealthMonitor>,
+    balancer: Arc<Balancer>,
+}
+
+impl ProxyState {
+    /// Proxy status, with routes failing their health check marked unhealthy.
+    fn status(&self, mut routes: Vec<Route>) -> ProxyStatus {
+        let mut unhealthy = BTreeMap::new();
+        for route in &mut routes {
+            if let Some(reason) = self.health.failure(route) {
+                route.state = RouteState::Unhealthy;
+                unhealthy.insert(route.target(), reason);
+            }
+        }
+        ProxyStatus {
+            pid: std::process::id(),
+            port: self.proxy_port,
+            https: self.https,
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            generation: self.routes.generation(),
+            routes,
+            unhealthy,
+        }
+    }
+}
+
+pub async fn run_proxy(config: ProxyConfig) -> Result<()> {
+    let ProxyConfig {
+        port,
+        state_dir,
+        https,
+        access_log,
+    } = config;
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
     let listener = TcpListener::bind(addr).await?;
 
+    // In HTTPS mode plain HTTP is still served on the same port (the first byte
+    // tells them apart), so `is_proxy_running` and old bookmarks keep working.
+    let tls_acceptor = if https {
+        Some(TlsAcceptor::from(crate::tls::server_config(&state_dir)?))
+    } else {
+        None
+    };
+
     // Write PID file so `portless proxy stop` and `shutdown_proxy_if_idle` can find us.
     let pid_path = state_dir.join("proxy.pid");
     let port_path = state_dir.join("proxy.port");
     let my_pid = std::process::id();
     let _ = std::fs::write(&pid_path, my_pid.to_string());
+    let started_at = Instant::now();
     let _ = std::fs::write(&port_path, port.to_string());
+    if https {
+        let _ = std::fs::write(https_marker_path(&state_dir), "");
+    } else {
+        let _ = std::fs::remove_file(https_marker_path(&state_dir));
+    }
 
-    eprintln!("portless proxy listening on port {}", port);
+    eprintln!(
+        "portless proxy listening on port {}{}",
+        port,
+        if https { " (HTTP + HTTPS)" } else { "" }
+    );
 
-    // `has_routes` is true while at least one live-PID route exists.
-    // The route-reloader writes to this channel; the idle-shutdown task reads from it.
+    // `has_routes` is true while at least one live-PID route or alias exists.
+    // The route watcher and liveness check write to this channel; the idle-shutdown task reads from it.
     let (routes_tx, mut routes_rx) = watch::channel(true);
+    let routes_tx = Arc::new(routes_tx);
+
+    let route_cache = Arc::new(RouteCache::new(RouteStore::new(state_dir.clone())?));
 
-    let store = RouteStore::new(state_dir.clone())?;
-    let cached_routes: Arc<RwLock<Vec<Route>>> = Arc::new(RwLock::new(
-        store.load_raw().unwrap_or_default(),
-    ));
+    let pool = Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST));
+    let stats = Arc::new(Stats::new());
+    let startup = Arc::new(StartupTracker::new());
+    let health = Arc::new(HealthMonitor::new());
+
+    // Route watcher: reloads the cache only when routes.json changes on disk.
+    {
+        let pool = pool.clone();
+        let stats = stats.clone();
+        let startup = startup.clone();
+        let health = health.clone();
+        let routes_tx = routes_tx.clone();
+        let cache = route_cache.clone();
+        spawn_route_watcher(route_cache.clone(), state_dir.clone(), move |routes| {
+            // Close pooled connections to ports no route points at anymore
+            pool.retain(|key| {
+                routes
+                    .iter()
+                    .any(|r| BackendKey::for_route(r) == *key)
+            });
+            stats.retain(|name| routes.iter().any(|r| r.name() == name));
+            startup.retain(routes);
+            health.retain(routes);
+            let _ = routes_tx.send(has_live_routes(routes));
+            eprintln!(
+                "portless proxy: routes reloaded (generation {}, {} route{})",
+                cache.generation(),
+                routes.len(),
+                if routes.len() == 1 { "" } else { "s" }
+            );
+        });
+    }
 
-    // Background route-reloader: re-reads routes.json every ROUTE_RELOAD_INTERVAL,
-    // updates the in-memory cache, and notifies the idle-shutdown task via the watch channel.
+    // Apps that exit without deregistering leave routes.json untouched, so PID
+    // liveness is checked separately against the in-memory table.
     {
-        let cached = cached_routes.clone();
-        let sd = state_dir.clone();
+        let cache = route_cache.clone();
         tokio::spawn(async move {
             loop {
-                tokio::time::sleep(ROUTE_RELOAD_INTERVAL).await;
-                if let Ok(s) = RouteStore::new(sd.clone()) {
-                    // load_raw for display cache (no PID filtering — fast path)
-                    if let Ok(routes) = s.load_raw()
-                        && let Ok(mut lock) = cached.write()
-                    {
-                        *lock = routes;
-                    }
-                    // load with PID filtering for idle-shutdown signal
-                    let alive = s.load(false).unwrap_or_default();
-                    let _ = routes_tx.send(!alive.is_empty());
-                }
+                tokio::time::sleep(LIVENESS_CHECK_INTERVAL).await;
+                let _ = routes_tx.send(has_live_routes(&cache.snapshot()));
             }
         });
     }
 
     // Idle-shutdown task: waits until `has_routes` is false, then arms a deadline.
     // If routes come back before the deadline, the timer is cancelled.
+    let idle_state_dir = state_dir.clone();
     tokio::spawn(async move {
         // Grace period: don't shut down before the first app has had time to register.
         tokio::time::sleep(IDLE_GRACE).await;
@@ -88,6 +215,7 @@ pub async fn run_proxy(port: u16, state_dir: PathBuf) -> Result<()> {
                 // Timer fires — still no routes, shut down.
                 _ = tokio::time::sleep_until(deadline) => {
                     eprintln!("portless proxy: no active routes — shutting down.");
+                    remove_state_files(&idle_state_dir);
                     std::process::exit(0);
                 }
                 // A new route appeared before the deadline — disarm and loop.
@@ -96,53 +224,504 @@ pub async fn run_proxy(port: u16, state_dir: PathBuf) -> Result<()> {
         }
     });
 
+    // Health checks catch apps that hang while their PID stays alive. Starting
+    // apps are left to the startup hold and their readiness probe.
+    {
+        let cache = route_cache.clone();
+        let health = health.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+                let routes: Vec<Route> = cache
+                    .snapshot()
+                    .iter()
+                    .filter(|r| r.state != RouteState::Starting && r.is_alive())
+                    .cloned()
+                    .collect();
+                health.check_all(&routes).await;
+            }
+        });
+    }
+
+    {
+        let pool = pool.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(POOL_REAP_INTERVAL).await;
+                pool.purge_expired();
+            }
+        });
+    }
+
+    {
+        let state_dir = state_dir.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(LOG_ROTATE_INTERVAL).await;
+                if let Err(e) = logfile::rotate_proxy_log(&state_dir) {
+                    eprintln!("portless proxy: cannot rotate proxy.log: {}", e);
+                }
+            }
+        });
+    }
+
+    let access_log = match access_log {
+        AccessLogFormat::Off => None,
+        format => match AccessLog::open(&state_dir, format) {
+            Ok(log) => Some(Arc::new(log)),
+            Err(e) => {
+                eprintln!("portless proxy: access log disabled: {}", e);
+                None
+            }
+        },
+    };
+    let mut captures = CaptureLog::new(CAPTURE_CAPACITY);
+    if let Some(log) = access_log.clone() {
+        captures = captures.with_observer(move |ex| log.log(&AccessEntry::from_exchange(ex)));
+    }
+
+    let state = Arc::new(ProxyState {
+        routes: route_cache,
+        pool,
+        proxy_port: port,
+        https,
+        state_dir: state_dir.clone(),
+        started_at,
+        stats,
+        captures: Arc::new(captures),
+        access_log,
+        metrics: Arc::new(Metrics::new()),
+        startup,
+        health,
+        balancer: Arc::new(Balancer::new()),
+    });
+
+    // Control socket for the CLI. A leftover socket from a crashed proxy is replaced;
+    // the TCP bind above already proved no other proxy owns this state dir.
+    let socket_path = control::socket_path(&state_dir);
+    let _ = std::fs::remove_file(&socket_path);
+    match UnixListener::bind(&socket_path) {
+        Ok(control_listener) => {
+            tokio::spawn(run_control_server(control_listener, state.clone()));
+        }
+        Err(e) => eprintln!(
+            "portless proxy: control socket unavailable ({}), CLI will use route files",
+            e
+        ),
+    }
+
+    // Clean up state files when stopped by `portless proxy stop` or Ctrl+C.
+    {
+        let state_dir = state_dir.clone();
+        let mut sigterm = signal(SignalKind::terminate())?;
+        let mut sigint = signal(SignalKind::interrupt())?;
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = sigterm.recv() => {}
+                _ = sigint.recv() => {}
+            }
+            remove_state_files(&state_dir);
+            std::process::exit(0);
+        });
+    }
+
     loop {
         let (stream, remote_addr) = listener.accept().await?;
-        let cached_routes = cached_routes.clone();
-        let proxy_port = port;
+        let state = state.clone();
+        let tls_acceptor = tls_acceptor.clone();
         tokio::spawn(async move {
-            let io = TokioIo::new(stream);
-            if let Err(e) = hyper::server::conn::http1::Builder::new()
-                .serve_connection(
-                    io,
-                    hyper::service::service_fn(move |req| {
-                        let routes = cached_routes
-                            .read()
-                            .map(|g| g.clone())
-                            .unwrap_or_default();
-                        handle_request(req, remote_addr, routes, proxy_port)
-                    }),
-                )
-                .with_upgrades()
-                .await
-            {
-                // Ignore connection reset errors
-                let msg = e.to_string();
-                if !msg.contains("connection reset") && !msg.contains("broken pipe") {
-                    eprintln!("connection error: {}", e);
+            match tls_acceptor {
+                Some(acceptor) if is_tls_handshake(&stream).await => {
+                    match acceptor.accept(stream).await {
+                        Ok(tls) => serve_connection(tls, remote_addr, state, "https").await,
+                        Err(e) => {
+                            // Browsers routinely abort speculative handshakes
+                            if e.kind() != std::io::ErrorKind::UnexpectedEof {
+                                eprintln!("TLS handshake error: {}", e);
+                            }
+                        }
+                    }
                 }
+                _ => serve_connection(stream, remote_addr, state, "http").await,
             }
         });
     }
 }
 
+/// Remove the files advertising this proxy, unless another proxy has taken them over.
+fn remove_state_files(state_dir: &Path) {
+    let pid_path = state_dir.join("proxy.pid");
+    if let Ok(pid) = std::fs::read_to_string(&pid_path)
+        && pid.trim() != std::process::id().to_string()
+    {
+        return;
+    }
+    let _ = std::fs::remove_file(&pid_path);
+    let _ = std::fs::remove_file(state_dir.join("proxy.port"));
+    let _ = std::fs::remove_file(https_marker_path(state_dir));
+    let _ = std::fs::remove_file(control::socket_path(state_dir));
+}
+
+async fn run_control_server(listener: UnixListener, state: Arc<ProxyState>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let state = state.clone();
+        tokio::spawn(async move {
+            let (reader, mut writer) = stream.into_split();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let (response, shutdown) = match control::decode::<ControlRequest>(&line) {
+                    Ok(ControlRequest::Tail { hostname, backlog }) => {
+                        tail_captures(&mut writer, &mut lines, &hostname, backlog, &state).await;
+                        break;
+                    }
+                    Ok(request) => {
+                        let shutdown = request == ControlRequest::Shutdown;
+                        (handle_control_request(request, &state).await, shutdown)
+                    }
+                    Err(e) => (
+                        ControlResponse::Error {
+                            message: e.to_string(),
+                        },
+                        false,
+                    ),
+                };
+                if !write_control_response(&mut writer, response).await {
+                    break;
+                }
+                if shutdown {
+                    let _ = writer.flush().await;
+                    eprintln!("portless proxy: shutdown requested — stopping.");
+                    remove_state_files(&state.state_dir);
+                    std::process::exit(0);
+                }
+            }
+        });
+    }
+}
+
+async fn write_control_response(writer: &mut OwnedWriteHalf, response: ControlResponse) -> bool {
+    let Ok(reply) = control::encode(response) else {
+        return false;
+    };
+    writer.write_all(reply.as_bytes()).await.is_ok()
+}
+
+/// Send recent and then live exchanges for `hostname` (or, for a wildcard, every
+/// hostname it matches) until the client disconnects.
+async fn tail_captures(
+    writer: &mut OwnedWriteHalf,
+    lines: &mut Lines<BufReader<OwnedReadHalf>>,
+    hostname: &str,
+    backlog: usize,
+    state: &ProxyState,
+) {
+    // Subscribe before reading the backlog so nothing completing in between is lost.
+    let mut live = state.captures.subscribe();
+    let mut sent_up_to = 0;
+    for capture in state.captures.recent(hostname, backlog) {
+        sent_up_to = capture.exchange.id;
+        let response = ControlResponse::Exchange(Box::new(capture.exchange.clone()));
+        if !write_control_response(writer, response).await {
+            return;
+        }
+    }
+
+    loop {
+        tokio::select! {
+            received = live.recv() => match received {
+                Ok(capture) => {
+                    if !hostname_matches(hostname, &capture.exchange.hostname)
+                        || capture.exchange.id <= sent_up_to
+                    {
+                        continue;
+                    }
+                    let response = ControlResponse::Exchange(Box::new(capture.exchange.clone()));
+                    if !write_control_response(writer, response).await {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+            // Anything from the client (including EOF) ends the tail.
+            _ = lines.next_line() => return,
+        }
+    }
+}
+
+/// Re-send a captured request to the current backend of its route.
+async fn replay_capture(id: u64, state: &ProxyState) -> ControlResponse {
+    let error = |message: String| ControlResponse::Error { message };
+
+    let Some(capture) = state.captures.get(id) else {
+        return error(format!("No captured request with id {}", id));
+    };
+    let ex = &capture.exchange;
+    if ex.request_body.truncated {
+        return error(format!(
+            "Request {} cannot be replayed: its {} byte body was only partially captured",
+            id, ex.request_body.size
+        ));
+    }
+    let routes = state.routes.snapshot();
+    let path = ex.uri.split('?').next().unwrap_or("/");
+    let Some(route) = find_route(&routes, &ex.hostname, path) else {
+        return error(format!("{}{} is not registered anymore", ex.hostname, path));
+    };
+
+    let mut builder = Request::builder().method(ex.method.as_str()).uri(ex.uri.as_str());
+    for (name, value) in &ex.request_headers {
+        builder = builder.header(name.as_str(), value.as_str());
+    }
+    // HTTP/2 clients send :authority instead of a Host header
+    if !ex.request_headers.iter().any(|(name, _)| name == "host") {
+        builder = builder.header("host", ex.hostname.as_str());
+    }
+    let body = Full::new(capture.request_body.clone())
+        .map_err(|e| match e {})
+        .boxed();
+    let req = match builder.body(body) {
+        Ok(req) => req,
+        Err(e) => return error(format!("Request {} cannot be replayed: {}", id, e)),
+    };
+
+    let replay_addr = SocketAddr::from(([127, 0, 0, 1], 0));
+    let (handle, mut req) = state.captures.begin(
+        &ex.hostname,
+        &ex.scheme,
+        replay_addr.ip(),
+        route.port,
+        req,
+        Some(id),
+    );
+    let new_id = handle.id();
+    rewrite_for_route(&mut req, route, &ex.hostname);
+    let key = BackendKey::for_route(route);
+    let started = Instant::now();
+    let result = handle_http(req, &key, &state.pool, replay_addr, "http").await;
+    record_result(state, &route.name(), started, &result);
+    match handle.finish(result) {
+        Ok(resp) => {
+            let status = resp.status().as_u16();
+            // Read the body so the exchange is completed and recorded.
+            tokio::spawn(resp.into_body().collect());
+            ControlResponse::Replayed { id: new_id, status }
+        }
+        Err(e) => error(format!("Replay failed: {}", e)),
+    }
+}
+
+async fn handle_control_request(request: ControlRequest, state: &ProxyState) -> ControlResponse {
+    match request {
+        ControlRequest::Register { route } => {
+            update_routes(state, move |store| store.add(route)).await
+        }
+        ControlRequest::Remove {
+            hostname,
+            path_prefix,
+            pid,
+        } => {
+            update_routes(state, move |store| {
+                store.remove(&hostname, path_prefix.as_deref(), pid)
+            })
+            .await
+        }
+        ControlRequest::Status => ControlResponse::Status(
+            state.status(
+                state
+                    .routes
+                    .snapshot()
+                    .iter()
+                    .filter(|r| r.is_alive())
+                    .cloned()
+                    .collect(),
+            ),
+        ),
+        ControlRequest::Version => ControlResponse::Version {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol: control::PROTOCOL_VERSION,
+        },
+        ControlRequest::Replay { id } => replay_capture(id, state).await,
+        ControlRequest::Captures { hostname, since_ms } => ControlResponse::Captures {
+            exchanges: state
+                .captures
+                .since(&hostname, since_ms)
+                .iter()
+                .map(|c| c.exchange.clone())
+                .collect(),
+        },
+        // Replied to by the caller, which then exits.
+        ControlRequest::Shutdown => ControlResponse::Ok,
+        // Streamed by the caller.
+        ControlRequest::Tail { .. } => ControlResponse::Error {
+            message: "tail must be the only request on a connection".to_string(),
+        },
+    }
+}
+
+/// Apply a change to routes.json and reload the cache before replying, so the
+/// route is live as soon as the CLI gets its answer.
+async fn update_routes<F>(state: &ProxyState, change: F) -> ControlResponse
+where
+    F: FnOnce(&RouteStore) -> Result<()> + Send + 'static,
+{
+    let state_dir = state.state_dir.clone();
+    let result = tokio::task::spawn_blocking(move || change(&RouteStore::new(state_dir)?)).await;
+    match result {
+        Ok(Ok(())) => {
+            state.routes.refresh();
+            ControlResponse::Ok
+        }
+        Ok(Err(e)) => ControlResponse::Error {
+            message: e.to_string(),
+        },
+        Err(e) => ControlResponse::Error {
+            message: e.to_string(),
+        },
+    }
+}
+
+fn has_live_routes(routes: &[Route]) -> bool {
+    routes.iter().any(Route::is_alive)
+}
+
+/// Peek at the first byte of a fresh connection to detect a TLS ClientHello.
+async fn is_tls_handshake(stream: &TcpStream) -> bool {
+    let mut first = [0u8; 1];
+    matches!(stream.peek(&mut first).await, Ok(1) if first[0] == TLS_HANDSHAKE_RECORD)
+}
+
+async fn serve_connection<I>(
+    io: I,
+    remote_addr: SocketAddr,
+    state: Arc<ProxyState>,
+    scheme: &'static str,
+) where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let _connection = state.metrics.track(Gauge::Connections);
+    let io = TokioIo::new(io);
+    // The auto builder sniffs the connection preface: HTTP/1.1, HTTP/2 negotiated via
+    // ALPN over TLS, and prior-knowledge h2c on the plain port are all accepted.
+    if let Err(e) = hyper_util::server::conn::auto::Builder::new(TokioExecutor::new())
+        .serve_connection_with_upgrades(
+            io,
+            hyper::service::service_fn(move |req| {
+                handle_request(req, remote_addr, state.clone(), scheme)
+            }),
+        )
+        .await
+    {
+        // Ignore connection reset errors
+        let msg = e.to_string();
+        if !msg.contains("connection reset") && !msg.contains("broken pipe") {
+            eprintln!("connection error: {}", e);
+        }
+    }
+}
+
+/// Who answered a request.
+enum Answer {
+    /// The proxy itself, e.g. with a 404 or the dashboard.
+    Proxy(Response<BoxBody<Bytes, hyper::Error>>),
+    /// An app; the access log gets the exchange once it completes.
+    App(Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error>),
+}
+
 async fn handle_request(
     req: Request<hyper::body::Incoming>,
     remote_addr: SocketAddr,
-    routes: Vec<Route>,
-    proxy_port: u16,
+    state: Arc<ProxyState>,
+    scheme: &'static str,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let hostname = extract_hostname(req.headers());
+    let entry = state.access_log.as_ref().map(|_| {
+        let hostname = extract_hostname(&req).unwrap_or_default();
+        AccessEntry::request(&req, &hostname, 0, remote_addr.ip())
+    });
+    let started = Instant::now();
+    match route_request(req, remote_addr, state.clone(), scheme).await {
+        Answer::App(result) => result,
+        Answer::Proxy(response) => {
+            if let (Some(log), Some(mut entry)) = (&state.access_log, entry) {
+                entry.status = response.status().as_u16();
+                entry.bytes = response.body().size_hint().exact().unwrap_or(0);
+                entry.duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+                log.log(&entry);
+            }
+            Ok(response)
+        }
+    }
+}
+
+async fn route_request(
+    req: Request<hyper::body::Incoming>,
+    remote_addr: SocketAddr,
+    state: Arc<ProxyState>,
+    scheme: &'static str,
+) -> Answer {
+    let hostname = extract_hostname(&req);
 
     let Some(host) = hostname else {
-        return Ok(bad_request_response("Missing Host header"));
+        return Answer::Proxy(bad_request_response("Missing Host header"));
     };
 
-    let target_port = routes.iter().find(|r| r.hostname == host).map(|r| r.port);
+    if host == DASHBOARD_HOSTNAME {
+        return Answer::Proxy(dashboard_request(&req, &state));
+    }
+
+    let path = req.uri().path().to_string();
+    let mut routes = state.routes.snapshot();
+    // An app may have registered moments ago, before the watcher event was handled.
+    if find_route(&routes, &host, &path).is_none() && state.routes.refresh() {
+        routes = state.routes.snapshot();
+    }
 
-    let Some(port) = target_port else {
-        return Ok(not_found_response(&routes, &host, proxy_port));
+    let Some(matched) = find_route(&routes, &host, &path) else {
+        return Answer::Proxy(not_found_response(
+            &routes,
+            &state.health,
+            &host,
+            &path,
+            state.proxy_port,
+            scheme == "https",
+        ));
     };
+    // Apps started with `--join` share the hostname and path with other instances.
+    let route = state
+        .balancer
+        .pick(&instances(&routes, matched), |r| {
+            r.state != RouteState::Starting && state.health.failure(r).is_none()
+        })
+        .unwrap_or(matched);
+    let port = route.port;
+    let name = route.name();
+
+    // A freshly started app may not be ready yet: hold the request until it
+    // is, or show browsers a page that reloads itself, instead of failing at once.
+    if let Some(deadline) = state.startup.deadline(route) {
+        if route.state != RouteState::Starting && startup::backend_accepts(route).await {
+            state.startup.mark_up(route);
+        } else if startup::is_navigation(req.method(), req.headers()) {
+            return Answer::Proxy(starting_response(&name, deadline));
+        } else if startup::wait_for_backend(&state.routes, route, deadline).await {
+            state.startup.mark_up(route);
+        }
+    }
+
+    // The app's process is alive but stopped answering its health check:
+    // say so instead of letting the request hang or fail with a bare 502.
+    if let Some(reason) = state.health.failure(route) {
+        let started = Instant::now();
+        let response = unhealthy_response(route, &reason);
+        record_status(&state, &name, started, response.status());
+        return Answer::Proxy(response);
+    }
+
+    // Counted from here, once the request is actually going to the instance.
+    let in_flight = state.balancer.track(route);
 
     let is_websocket = req
         .headers()
@@ -152,40 +731,156 @@ async fn handle_request(
         .unwrap_or(false);
 
     if is_websocket {
-        return handle_websocket(req, port, remote_addr).await;
+        let entry = state
+            .access_log
+            .as_ref()
+            .map(|_| AccessEntry::websocket(&req, &host, port, remote_addr.ip()));
+        let mut req = req;
+        rewrite_for_route(&mut req, route, &host);
+        let started = Instant::now();
+        let key = BackendKey::for_route(route);
+        let result = handle_websocket(
+            req,
+            &key,
+            remote_addr,
+            scheme,
+            state.metrics.clone(),
+            in_flight,
+        )
+        .await;
+        record_result(&state, &name, started, &result);
+        if let (Some(log), Some(mut entry)) = (&state.access_log, entry) {
+            entry.status = match &result {
+                Ok(resp) => resp.status().as_u16(),
+                Err(_) => StatusCode::BAD_GATEWAY.as_u16(),
+            };
+            entry.duration_ms = started.elapsed().as_secs_f64() * 1000.0;
+            log.log(&entry);
+        }
+        return Answer::App(result);
     }
 
-    handle_http(req, port, remote_addr).await
+    let key = BackendKey::for_route(route);
+    let (capture, mut req) = state
+        .captures
+        .begin(&host, scheme, remote_addr.ip(), port, req, None);
+    rewrite_for_route(&mut req, route, &host);
+    let started = Instant::now();
+    let result = handle_http(req, &key, &state.pool, remote_addr, scheme).await;
+    record_result(&state, &name, started, &result);
+    // The request counts as in flight until its response body is done.
+    Answer::App(capture.finish(result).map(|resp| {
+        resp.map(|body| {
+            body.map_frame(move |frame| {
+                let _ = &in_flight;
+                frame
+            })
+            .boxed()
+        })
+    }))
 }
 
-async fn handle_http(
-    req: Request<hyper::body::Incoming>,
-    port: u16,
-    remote_addr: SocketAddr,
-) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
-    let stream = match connect_to_backend(port).await {
-        Ok(s) => s,
-        Err(_) => return Ok(bad_gateway_response()),
+/// Adapt a request for `hostname` to the route it matched: pass the labels a
+/// wildcard route matched in `X-Portless-Tenant`, and forward requests to a
+/// route registered with `--strip-prefix` without its path prefix, telling the
+/// app what was removed in `X-Forwarded-Prefix`.
+fn rewrite_for_route<B>(req: &mut Request<B>, route: &Route, hostname: &str) {
+    // Only the proxy sets the tenant; a client-supplied value is never passed on.
+    req.headers_mut().remove(TENANT_HEADER);
+    if let Some(tenant) = wildcard_label(&route.hostname, hostname)
+        && let Ok(value) = HeaderValue::from_str(tenant)
+    {
+        req.headers_mut().insert(TENANT_HEADER, value);
+    }
+
+    let Some(prefix) = route.path_prefix.as_deref().filter(|_| route.strip_prefix) else {
+        return;
+    };
+    let path = route.backend_path(req.uri().path_and_query().map_or("/", |p| p.as_str()));
+    let mut parts = req.uri().clone().into_parts();
+    parts.path_and_query = path.parse().ok();
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *req.uri_mut() = uri;
+    }
+    if let Ok(value) = HeaderValue::from_str(prefix) {
+        req.headers_mut().insert("x-forwarded-prefix", value);
+    }
+}
+
+/// Count a response in the stats and metrics of the route called `name`.
+fn record_result<B>(
+    state: &ProxyState,
+    name: &str,
+    started: Instant,
+    result: &Result<Response<B>, hyper::Error>,
+) {
+    let status = match result {
+        Ok(resp) => {
+            if resp.extensions().get::<BackendUnreachable>().is_some() {
+                state.metrics.record_connect_failure(name);
+            }
+            resp.status()
+        }
+        Err(_) => StatusCode::BAD_GATEWAY,
     };
+    record_status(state, name, started, status);
+}
+
+fn record_status(state: &ProxyState, name: &str, started: Instant, status: StatusCode) {
+    state.stats.record(name, status);
+    state.metrics.record(name, status, started.elapsed());
+}
 
-    let io = TokioIo::new(stream);
-    let (mut sender, conn) = hyper::client::conn::http1::handshake(io).await?;
-    tokio::spawn(conn);
+/// Requests for the reserved dashboard hostname are answered by the proxy itself.
+fn dashboard_request<B>(
+    req: &Request<B>,
+    state: &Arc<ProxyState>,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    match req.uri().path() {
+        "/" => dashboard::page_response(),
+        "/api/routes" => dashboard::json_response(&dashboard::snapshot(
+            &state.status(state.routes.snapshot().to_vec()),
+            &state.stats,
+        )),
+        "/metrics" => {
+            let routes = state.routes.snapshot();
+            let text = state.metrics.render(routes.len(), state.routes.generation());
+            dashboard::metrics_response(text)
+        }
+        "/events" => {
+            let state = state.clone();
+            dashboard::events_response(move || {
+                dashboard::snapshot(&state.status(state.routes.snapshot().to_vec()), &state.stats)
+            })
+        }
+        _ => dashboard::not_found_response(),
+    }
+}
 
+async fn handle_http(
+    req: Request<ProxyBody>,
+    key: &BackendKey,
+    pool: &Arc<BackendPool>,
+    remote_addr: SocketAddr,
+    scheme: &'static str,
+) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
     let (mut parts, body) = req.into_parts();
 
     let client_ip = remote_addr.ip().to_string();
+    // HTTP/2 clients send :authority instead of a Host header
     let host_val = parts
         .headers
         .get("host")
         .and_then(|v| v.to_str().ok())
-        .unwrap_or("")
-        .to_string();
+        .map(str::to_string)
+        .or_else(|| parts.uri.authority().map(|a| a.to_string()))
+        .unwrap_or_default();
 
-    // Rewrite Host to localhost:<port> so backends like Vite accept the request
+    // Rewrite Host to what the backend expects: localhost:<port> so dev servers
+    // like Vite accept the request, or an upstream's own host name
     parts.headers.insert(
         "host",
-        HeaderValue::from_str(&format!("localhost:{}", port))
+        HeaderValue::from_str(&key.authority())
             .unwrap_or_else(|_| HeaderValue::from_static("localhost")),
     );
 
@@ -201,11 +896,11 @@ async fn handle_http(
         HeaderValue::from_str(&xff).unwrap_or_else(|_| HeaderValue::from_static("unknown")),
     );
 
-    // X-Forwarded-Proto: preserve existing or default to "http"
+    // X-Forwarded-Proto: preserve existing or default to the client connection's scheme
     if !parts.headers.contains_key("x-forwarded-proto") {
         parts
             .headers
-            .insert("x-forwarded-proto", HeaderValue::from_static("http"));
+            .insert("x-forwarded-proto", HeaderValue::from_static(scheme));
     }
 
     // X-Forwarded-Host: preserve existing or set to Host header
@@ -219,10 +914,11 @@ async fn handle_http(
 
     // X-Forwarded-Port: preserve existing or extract from Host header
     if !parts.headers.contains_key("x-forwarded-port") {
+        let default_port = if scheme == "https" { "443" } else { "80" };
         let fwd_port = host_val
             .split(':')
             .nth(1)
-            .unwrap_or("80")
+            .unwrap_or(default_port)
             .to_string();
         parts.headers.insert(
             "x-forwarded-port",
@@ -231,8 +927,18 @@ async fn handle_http(
         );
     }
 
+    if key.http2 {
+        prepare_http2_request(&mut parts, key);
+    } else {
+        prepare_http1_request(&mut parts);
+    }
+
     let req = Request::from_parts(parts, body);
-    let mut response = sender.send_request(req).await?;
+    let mut response = match pool.send(key, req).await {
+        Ok(r) => r,
+        Err(PoolError::Connect(e)) => return Ok(unreachable_response(key, &e)),
+        Err(PoolError::Http(e)) => return Err(e),
+    };
 
     response
         .headers_mut()
@@ -242,12 +948,65 @@ async fn handle_http(
     Ok(Response::from_parts(parts, body.boxed()))
 }
 
+/// Hop-by-hop headers that HTTP/2 forbids (RFC 9113 §8.2.2).
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// HTTP/1 backends expect an origin-form target, even if the client spoke HTTP/2.
+fn prepare_http1_request(parts: &mut hyper::http::request::Parts) {
+    parts.version = Version::HTTP_11;
+    if parts.uri.authority().is_some() {
+        let path = parts
+            .uri
+            .path_and_query()
+            .map(|p| p.as_str())
+            .unwrap_or("/");
+        if let Ok(uri) = path.parse::<Uri>() {
+            parts.uri = uri;
+        }
+    }
+}
+
+/// HTTP/2 backends need an absolute URI (`:scheme` + `:authority`) and no connection headers.
+fn prepare_http2_request(parts: &mut hyper::http::request::Parts, key: &BackendKey) {
+    parts.version = Version::HTTP_2;
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|p| p.as_str())
+        .unwrap_or("/");
+    if let Ok(uri) = format!("{}://{}{}", key.scheme(), key.authority(), path).parse::<Uri>() {
+        parts.uri = uri;
+    }
+    // :authority carries the rewritten host
+    parts.headers.remove("host");
+    for name in CONNECTION_HEADERS {
+        parts.headers.remove(*name);
+    }
+    // `te` is only allowed with the value "trailers" (which gRPC relies on)
+    if parts
+        .headers
+        .get("te")
+        .is_some_and(|v| v.as_bytes() != b"trailers")
+    {
+        parts.headers.remove("te");
+    }
+}
+
 /// WebSocket proxy: connect to backend using http upgrade, forward the backend's
 /// actual 101 headers (including Sec-WebSocket-Accept), then tunnel bidirectionally.
 async fn handle_websocket(
     req: Request<hyper::body::Incoming>,
-    port: u16,
+    key: &BackendKey,
     remote_addr: SocketAddr,
+    scheme: &'static str,
+    metrics: Arc<Metrics>,
+    in_flight: InFlight,
 ) -> Result<Response<BoxBody<Bytes, hyper::Error>>, hyper::Error> {
     use tokio::io::AsyncWriteExt;
 
@@ -267,9 +1026,8 @@ async fn handle_websocket(
 
     // Build raw HTTP request to send to backend
     let mut req_str = format!("{} {} HTTP/1.1\r\n", method, uri_path);
-    // Rewrite Host to localhost:<port> so backends like Vite accept the request;
-    // skip the original Host header from the client.
-    req_str.push_str(&format!("host: localhost:{}\r\n", port));
+    // Rewrite Host as for HTTP requests; skip the original Host header from the client.
+    req_str.push_str(&format!("host: {}\r\n", key.authority()));
     for (name, value) in req.headers() {
         if name == "host" {
             continue;
@@ -290,13 +1048,18 @@ async fn handle_websocket(
         req_str.push_str(&format!("x-forwarded-host: {}\r\n", host));
     }
     if req.headers().get("x-forwarded-proto").is_none() {
-        req_str.push_str("x-forwarded-proto: http\r\n");
+        req_str.push_str(&format!("x-forwarded-proto: {}\r\n", scheme));
     }
     req_str.push_str("\r\n");
 
-    let mut backend = match connect_to_backend(port).await {
+    // The upgrade is HTTP/1.1, even to backends otherwise spoken to over HTTP/2.
+    let key = BackendKey {
+        http2: false,
+        ..key.clone()
+    };
+    let mut backend = match pool::connect(&key).await {
         Ok(s) => s,
-        Err(_) => return Ok(bad_gateway_response()),
+        Err(e) => return Ok(unreachable_response(&key, &e)),
     };
 
     if backend.write_all(req_str.as_bytes()).await.is_err() {
@@ -323,6 +1086,9 @@ async fn handle_websocket(
                 return;
             }
         };
+        let _tunnel = metrics.track(Gauge::WebSockets);
+        // The socket counts against the instance for least-conn until it closes.
+        let _in_flight = in_flight;
         let mut client_io = TokioIo::new(upgraded_client);
         let _ = tokio::io::copy_bidirectional(&mut client_io, &mut backend).await;
     });
@@ -340,7 +1106,7 @@ async fn handle_websocket(
 
 /// Read HTTP response headers from a raw TCP stream byte-by-byte until \r\n\r\n.
 async fn read_http_headers(
-    stream: &mut TcpStream,
+    stream: &mut (impl AsyncRead + Unpin),
 ) -> anyhow::Result<(u16, Vec<(String, String)>)> {
     let mut buf: Vec<u8> = Vec::with_capacity(1024);
     let mut byte = [0u8; 1];
@@ -374,32 +1140,52 @@ async fn read_http_headers(
     Ok((status, headers))
 }
 
-fn extract_hostname(headers: &hyper::HeaderMap) -> Option<String> {
-    headers
+/// Hostname from the Host header, or the `:authority` pseudo-header for HTTP/2.
+fn extract_hostname<B>(req: &Request<B>) -> Option<String> {
+    req.headers()
         .get("host")
         .and_then(|v| v.to_str().ok())
         .map(|host| host.split(':').next().unwrap_or(host).to_ascii_lowercase())
+        .or_else(|| req.uri().host().map(|h| h.to_ascii_lowercase()))
         .filter(|h| !h.is_empty())
 }
 
 fn not_found_response(
     routes: &[Route],
+    health: &HealthMonitor,
     hostname: &str,
+    path: &str,
     proxy_port: u16,
+    https: bool,
 ) -> Response<BoxBody<Bytes, hyper::Error>> {
-    let safe_host = escape_html(hostname);
+    // Only some paths of this hostname are registered: name the path that missed.
+    let safe_host = if routes.iter().any(|r| r.hostname == hostname) {
+        escape_html(&format!("{}{}", hostname, path))
+    } else {
+        escape_html(hostname)
+    };
     let routes_html = if routes.is_empty() {
         "<p><em>No apps running.</em></p>".to_string()
     } else {
         let items: String = routes
             .iter()
             .map(|r| {
-                let safe_h = escape_html(&r.hostname);
-                let url = crate::utils::format_url(&r.hostname, proxy_port);
+                let safe_h = escape_html(&r.name());
+                let url = crate::utils::format_route_url(r, proxy_port, https);
                 let safe_url = escape_html(&url);
+                let health = match health.failure(r) {
+                    Some(reason) => format!(
+                        " <span style=\"color: #cf222e;\" title=\"{}\">(unhealthy)</span>",
+                        escape_html(&reason)
+                    ),
+                    None => String::new(),
+                };
                 format!(
-                    "<li><a href=\"{}\">{}</a> - localhost:{}</li>",
-                    safe_url, safe_h, r.port
+                    "<li><a href=\"{}\">{}</a> - {}{}</li>",
+                    safe_url,
+                    safe_h,
+                    escape_html(&r.target()),
+                    health
                 )
             })
             .collect();
@@ -416,11 +1202,13 @@ fn not_found_response(
     <h2>Active apps:</h2>
     {routes_html}
     <p>Start an app with: <code>portless {name} your-command</code></p>
+    <p><a href="{dashboard}">Open the portless dashboard</a></p>
   </body>
 </html>"#,
         safe_host = safe_host,
         routes_html = routes_html,
         name = escape_html(&name_hint),
+        dashboard = escape_html(&crate::utils::format_url(DASHBOARD_HOSTNAME, proxy_port, https)),
     );
 
     let mut resp = Response::new(
@@ -438,6 +1226,90 @@ fn not_found_response(
     resp
 }
 
+/// Shown to browsers while an app is still starting; reloads itself every second.
+fn starting_response(
+    hostname: &str,
+    deadline: Instant,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let name = escape_html(hostname.trim_end_matches(".localhost"));
+    let body = format!(
+        r#"<html>
+  <head>
+    <title>portless - Starting {name}</title>
+    <meta http-equiv="refresh" content="1">
+  </head>
+  <body style="font-family: system-ui; padding: 40px; max-width: 600px; margin: 0 auto;">
+    <h1>Waiting for {name} to start&hellip;</h1>
+    <p>This page reloads automatically once <strong>{host}</strong> is listening.</p>
+    <p style="color: #888;">Giving up in {secs}s.</p>
+  </body>
+</html>"#,
+        name = name,
+        host = escape_html(hostname),
+        secs = deadline.saturating_duration_since(Instant::now()).as_secs().max(1),
+    );
+
+    let mut resp = Response::new(
+        Full::new(Bytes::from(body))
+            .map_err(|e| match e {})
+            .boxed(),
+    );
+    *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    resp.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    resp.headers_mut()
+        .insert("retry-after", HeaderValue::from_static("1"));
+    resp.headers_mut()
+        .insert("cache-control", HeaderValue::from_static("no-store"));
+    resp.headers_mut()
+        .insert("x-portless", HeaderValue::from_static("1"));
+    resp
+}
+
+/// Served while a route fails its health check, in place of a 502.
+fn unhealthy_response(route: &Route, reason: &str) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let process = if route.alias {
+        "It is an alias for that port; check whatever serves it.".to_string()
+    } else if is_pid_alive(route.pid) {
+        format!("Its process (pid {}) is still running but not answering.", route.pid)
+    } else {
+        format!("Its process (pid {}) has exited.", route.pid)
+    };
+    let body = format!(
+        r#"<html>
+  <head><title>portless - {host} is unhealthy</title></head>
+  <body style="font-family: system-ui; padding: 40px; max-width: 600px; margin: 0 auto;">
+    <h1>Backend unhealthy</h1>
+    <p><strong>{host}</strong> (localhost:{port}) is failing its health check: {reason}.</p>
+    <p>{process}</p>
+    <p style="color: #888;">Requests go through again as soon as a check passes.</p>
+  </body>
+</html>"#,
+        host = escape_html(&route.hostname),
+        port = route.port,
+        reason = escape_html(reason),
+        process = process,
+    );
+
+    let mut resp = Response::new(
+        Full::new(Bytes::from(body))
+            .map_err(|e| match e {})
+            .boxed(),
+    );
+    *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    resp.headers_mut().insert(
+        "content-type",
+        HeaderValue::from_static("text/html; charset=utf-8"),
+    );
+    resp.headers_mut()
+        .insert("cache-control", HeaderValue::from_static("no-store"));
+    resp.headers_mut()
+        .insert("x-portless", HeaderValue::from_static("1"));
+    resp
+}
+
 fn bad_request_response(msg: &'static str) -> Response<BoxBody<Bytes, hyper::Error>> {
     let mut resp = Response::new(
         Full::new(Bytes::from(msg))
@@ -452,6 +1324,38 @@ fn bad_request_response(msg: &'static str) -> Response<BoxBody<Bytes, hyper::Err
     resp
 }
 
+/// A 502 for a backend that could not be connected to at all. For a remote
+/// upstream the message names it, and a connection that timed out is a 504.
+fn unreachable_response(
+    key: &BackendKey,
+    err: &std::io::Error,
+) -> Response<BoxBody<Bytes, hyper::Error>> {
+    let mut resp = match &key.upstream {
+        Some(upstream) => {
+            let (status, reason) = if err.kind() == std::io::ErrorKind::TimedOut {
+                (StatusCode::GATEWAY_TIMEOUT, "Gateway Timeout")
+            } else {
+                (StatusCode::BAD_GATEWAY, "Bad Gateway")
+            };
+            let body = format!("{reason}: could not connect to {upstream} ({err}).");
+            let mut resp = Response::new(
+                Full::new(Bytes::from(body))
+                    .map_err(|e| match e {})
+                    .boxed(),
+            );
+            *resp.status_mut() = status;
+            resp.headers_mut()
+                .insert("content-type", HeaderValue::from_static("text/plain"));
+            resp.headers_mut()
+                .insert("x-portless", HeaderValue::from_static("1"));
+            resp
+        }
+        None => bad_gateway_response(),
+    };
+    resp.extensions_mut().insert(BackendUnreachable);
+    resp
+}
+
 fn bad_gateway_response() -> Response<BoxBody<Bytes, hyper::Error>> {
     let body = "Bad Gateway: the target app may not be running.";
     let mut resp = Response::new(
@@ -471,12 +1375,256 @@ fn empty_body() -> BoxBody<Bytes, hyper::Error> {
     Empty::<Bytes>::new().map_err(|e| match e {}).boxed()
 }
 
-/// Connect to a local backend port, trying IPv4 first then IPv6.
-/// Node.js 18+ on macOS resolves `localhost` to ::1 (IPv6) by default,
-/// so hardcoding 127.0.0.1 fails when the backend only binds to ::1.
-async fn connect_to_backend(port: u16) -> std::io::Result<TcpStream> {
-    if let Ok(s) = TcpStream::connect(("127.0.0.1", port)).await {
-        return Ok(s);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls;
+    use crate::types::{Balance, Upstream};
+    use hyper::body::Incoming;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tempfile::TempDir;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, ServerName};
+    use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+    use tokio_rustls::TlsConnector;
+
+    /// Start an HTTP/2-only (h2c) backend that counts accepted TCP connections
+    /// and answers with the protocol version and path it saw.
+    async fn h2c_backend() -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let service = hyper::service::service_fn(|req: Request<Incoming>| {
+                        let body = format!("{:?} {}", req.version(), req.uri().path());
+                        async move {
+                            Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(body))))
+                        }
+                    });
+                    let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        (port, accepted)
+    }
+
+    /// An `app.localhost` route to `port`, owned by this process.
+    fn app_route(port: u16) -> Route {
+        Route {
+            hostname: "app.localhost".to_string(),
+            port,
+            pid: std::process::id(),
+            ..Default::default()
+        }
+    }
+
+    /// Proxy state for a state dir holding `routes`.
+    fn proxy_state(state_dir: &Path, routes: &[Route]) -> Arc<ProxyState> {
+        let store = RouteStore::new(state_dir.to_path_buf()).unwrap();
+        store.save(routes).unwrap();
+        Arc::new(ProxyState {
+            routes: Arc::new(RouteCache::new(store)),
+            pool: Arc::new(BackendPool::new(POOL_IDLE_TIMEOUT, POOL_MAX_IDLE_PER_HOST)),
+            proxy_port: 1355,
+            https: false,
+            state_dir: state_dir.to_path_buf(),
+            started_at: Instant::now(),
+            stats: Arc::new(Stats::new()),
+            captures: Arc::new(CaptureLog::new(CAPTURE_CAPACITY)),
+            access_log: None,
+            metrics: Arc::new(Metrics::new()),
+            startup: Arc::new(StartupTracker::new()),
+            health: Arc::new(HealthMonitor::new()),
+            balancer: Arc::new(Balancer::new()),
+        })
+    }
+
+    /// Serve `state` on a fresh port, over TLS when given an acceptor.
+    async fn start_proxy(state: Arc<ProxyState>, tls: Option<TlsAcceptor>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (stream, remote_addr) = listener.accept().await.unwrap();
+                let state = state.clone();
+                let tls = tls.clone();
+                tokio::spawn(async move {
+                    match tls {
+                        Some(acceptor) => {
+                            let stream = acceptor.accept(stream).await.unwrap();
+                            serve_connection(stream, remote_addr, state, "https").await
+                        }
+                        None => serve_connection(stream, remote_addr, state, "http").await,
+                    }
+                });
+            }
+        });
+        port
+    }
+
+    /// GET `path` from `app.localhost` over HTTP/2, twice on the same
+    /// connection, returning each response's version and body.
+    async fn h2_get_twice<I>(io: I, scheme: &str, path: &str) -> Vec<(Version, String)>
+    where
+        I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut sender, conn) =
+            hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(io))
+                .await
+                .unwrap();
+        tokio::spawn(conn);
+        let mut answers = Vec::new();
+        for _ in 0..2 {
+            let req = Request::get(format!("{}://app.localhost{}", scheme, path))
+                .body(Empty::<Bytes>::new())
+                .unwrap();
+            let resp = sender.send_request(req).await.unwrap();
+            let version = resp.version();
+            let body = resp.into_body().collect().await.unwrap().to_bytes();
+            answers.push((version, String::from_utf8_lossy(&body).into_owned()));
+        }
+        answers
+    }
+
+    #[tokio::test]
+    async fn test_h2c_client_reaches_h2c_backend() {
+        let temp = TempDir::new().unwrap();
+        let (backend, accepted) = h2c_backend().await;
+        let route = Route {
+            h2c: true,
+            ..app_route(backend)
+        };
+        let port = start_proxy(proxy_state(temp.path(), &[route]), None).await;
+
+        // Prior-knowledge h2c on the plain port.
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let answers = h2_get_twice(stream, "http", "/hello").await;
+        let expected = (Version::HTTP_2, "HTTP/2.0 /hello".to_string());
+        assert_eq!(answers, [expected.clone(), expected]);
+
+        // Both requests went over the pool's shared connection to the backend.
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_alpn_h2_over_tls() {
+        let temp = TempDir::new().unwrap();
+        let (backend, accepted) = h2c_backend().await;
+        let acceptor = TlsAcceptor::from(tls::server_config(temp.path()).unwrap());
+        let route = Route {
+            h2c: true,
+            ..app_route(backend)
+        };
+        let port = start_proxy(proxy_state(temp.path(), &[route]), Some(acceptor)).await;
+
+        let ca = tls::LocalCa::load_or_create(temp.path()).unwrap();
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(ca.cert_der().to_vec())).unwrap();
+        let mut config = ClientConfig::clone(&tls::client_config(roots));
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("app.localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let answers = h2_get_twice(stream, "https", "/").await;
+        let expected = (Version::HTTP_2, "HTTP/2.0 /".to_string());
+        assert_eq!(answers, [expected.clone(), expected]);
+        assert_eq!(accepted.load(Ordering::SeqCst), 1);
+    }
+
+    /// Start a backend that accepts every WebSocket upgrade and then keeps
+    /// the socket open until the client closes it.
+    async fn websocket_backend() -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 1024];
+                    let _ = stream.read(&mut buf).await;
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 101 Switching Protocols\r\nupgrade: websocket\r\n\r\n",
+                        )
+                        .await;
+                    while stream.read(&mut buf).await.is_ok_and(|n| n > 0) {}
+                });
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_upstream_is_named() {
+        let temp = TempDir::new().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = listener.local_addr().unwrap().port();
+        drop(listener);
+        let upstream = Upstream {
+            host: "127.0.0.1".to_string(),
+            port: closed,
+            tls: false,
+        };
+        let route = Route {
+            alias: true,
+            pid: 0,
+            upstream: Some(upstream.clone()),
+            ..app_route(closed)
+        };
+        let port = start_proxy(proxy_state(temp.path(), &[route]), None).await;
+
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nhost: app.localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut answer = String::new();
+        client.read_to_string(&mut answer).await.unwrap();
+        assert!(answer.starts_with("HTTP/1.1 502"), "{answer}");
+        assert!(answer.contains(&format!("could not connect to {upstream}")), "{answer}");
+    }
+
+    #[tokio::test]
+    async fn test_open_websockets_count_for_least_conn() {
+        let temp = TempDir::new().unwrap();
+        let joined = |port| Route {
+            join: true,
+            balance: Some(Balance::LeastConn),
+            ..app_route(port)
+        };
+        let routes = [joined(websocket_backend().await), joined(websocket_backend().await)];
+        let state = proxy_state(temp.path(), &routes);
+        let port = start_proxy(state.clone(), None).await;
+
+        let mut client = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        client
+            .write_all(
+                b"GET /socket HTTP/1.1\r\nhost: app.localhost\r\n\
+                  connection: upgrade\r\nupgrade: websocket\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut buf = [0u8; 1024];
+        let n = client.read(&mut buf).await.unwrap();
+        assert!(buf[..n].starts_with(b"HTTP/1.1 101"));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // The socket went to the first instance and keeps it busy while open.
+        let all: Vec<&Route> = routes.iter().collect();
+        assert_eq!(state.balancer.pick(&all, |_| true).unwrap().port, routes[1].port);
+
+        drop(client);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(state.balancer.pick(&all, |_| true).unwrap().port, routes[0].port);
     }
-    TcpStream::connect(("::1", port)).await
 }
@@ -0,0 +1,245 @@
+use anyhow::{anyhow, Result};
+use http_body_util::Empty;
+use hyper::body::Bytes;
+use hyper::client::conn::http2;
+use hyper::header::USER_AGENT;
+use hyper::Request;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use regex::Regex;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::Notify;
+use tokio::time::Duration;
+
+use crate::output::{strip_ansi, AppOutput};
+use crate::pool::{self, BackendKey, BackendStream};
+
+/// Pause between readiness probes.
+const PROBE_INTERVAL: Duration = Duration::from_millis(200);
+/// Bytes of an HTTP probe response read before giving up on it.
+const PROBE_RESPONSE_LIMIT: u64 = 64 * 1024;
+
+/// How `portless <name>` decides that the app can take requests.
+#[derive(Debug, Clone)]
+pub enum ReadyProbe {
+    /// Something accepts connections on the app's port.
+    Tcp,
+    /// `GET <path>` answers with a 2xx or 3xx status.
+    Http(String),
+    /// A line of the app's output matches.
+    Log(Regex),
+}
+
+/// Parse `tcp`, `http`, `http:/path` or `log:<regex>`.
+pub fn parse_probe(input: &str) -> Result<ReadyProbe> {
+    match input.split_once(':') {
+        None if input == "tcp" => Ok(ReadyProbe::Tcp),
+        None if input == "http" => Ok(ReadyProbe::Http("/".to_string())),
+        Some(("http", path)) if path.starts_with('/') => Ok(ReadyProbe::Http(path.to_string())),
+        Some(("http", path)) => Err(anyhow!("HTTP probe path must start with '/': {}", path)),
+        Some(("log", pattern)) => Regex::new(pattern)
+            .map(ReadyProbe::Log)
+            .map_err(|e| anyhow!("Invalid log pattern: {}", e)),
+        _ => Err(anyhow!(
+            "Unknown readiness probe '{}' (expected tcp, http:/path or log:<regex>)",
+            input
+        )),
+    }
+}
+
+impl ReadyProbe {
+    /// Wait until the app at `backend` passes the probe. For [`ReadyProbe::Log`],
+    /// `log_matched` is notified by [`forward_output`].
+    pub async fn wait(&self, backend: &BackendKey, log_matched: &Notify) {
+        match self {
+            ReadyProbe::Tcp => {
+                while pool::connect(backend).await.is_err() {
+                    tokio::time::sleep(PROBE_INTERVAL).await;
+                }
+            }
+            ReadyProbe::Http(path) => {
+                while !http_status(backend, path)
+                    .await
+                    .is_some_and(|status| (200..400).contains(&status))
+                {
+                    tokio::time::sleep(PROBE_INTERVAL).await;
+                }
+            }
+            ReadyProbe::Log(_) => log_matched.notified().await,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            ReadyProbe::Tcp => "port accepts connections".to_string(),
+            ReadyProbe::Http(path) => format!("GET {} succeeds", path),
+            ReadyProbe::Log(pattern) => format!("output matches /{}/", pattern),
+        }
+    }
+
+    pub fn pattern(&self) -> Option<&Regex> {
+        match self {
+            ReadyProbe::Log(pattern) => Some(pattern),
+            _ => None,
+        }
+    }
+}
+
+/// Status code of `GET <path>` on the backend, if it answered at all.
+pub async fn http_status(backend: &BackendKey, path: &str) -> Option<u16> {
+    let stream = pool::connect(backend).await.ok()?;
+    http_status_over(stream, backend, path).await
+}
+
+/// Status code of `GET <path>` sent over `stream`, a new connection to
+/// `backend`: in HTTP/2 to h2c backends, else in HTTP/1.1.
+pub async fn http_status_over(
+    mut stream: BackendStream,
+    backend: &BackendKey,
+    path: &str,
+) -> Option<u16> {
+    if backend.http2 {
+        let (mut sender, conn) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .ok()?;
+        tokio::spawn(conn);
+        let uri = format!("{}://{}{}", backend.scheme(), backend.authority(), path);
+        let request = Request::get(uri)
+            .header(USER_AGENT, "portless")
+            .body(Empty::<Bytes>::new())
+            .ok()?;
+        return Some(sender.send_request(request).await.ok()?.status().as_u16());
+    }
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: portless\r\nConnection: close\r\n\r\n",
+        path,
+        backend.authority()
+    );
+    stream.write_all(request.as_bytes()).await.ok()?;
+
+    // Read the whole (capped) response so the app doesn't see the connection
+    // reset mid-write; only the status in "HTTP/1.1 200 OK" matters.
+    let mut response = Vec::new();
+    stream
+        .take(PROBE_RESPONSE_LIMIT)
+        .read_to_end(&mut response)
+        .await
+        .ok()?;
+    std::str::from_utf8(response.get(9..12)?).ok()?.parse().ok()
+}
+
+/// Copy the app's output line by line to our stdout (or stderr) and its log
+/// file, notifying `matched` once a line matches `pattern`. Colours are
+/// ignored when matching.
+pub async fn forward_output<R>(
+    reader: R,
+    to_stderr: bool,
+    output: Arc<AppOutput>,
+    mut pattern: Option<Regex>,
+    matched: Arc<Notify>,
+) where
+    R: AsyncRead + Unpin,
+{
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let shown = output.display(&line, now);
+        let written = if to_stderr {
+            tokio::io::stderr().write_all(&shown).await
+        } else {
+            tokio::io::stdout().write_all(&shown).await
+        };
+        if written.is_err() {
+            return;
+        }
+        let text = String::from_utf8_lossy(&line);
+        let text = text.trim_end_matches(['\r', '\n']);
+        output.log(text, now);
+        if let Some(p) = &pattern
+            && p.is_match(&strip_ansi(text))
+        {
+            matched.notify_one();
+            pattern = None;
+        }
+    }
+}
+
+/// Open `url` in the default browser, without waiting for it.
+pub fn open_browser(url: &str) -> Result<()> {
+    let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+    std::process::Command::new(opener)
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(drop)
+        .map_err(|e| anyhow!("Could not run {}: {}", opener, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_parse_probe() {
+        assert!(matches!(parse_probe("tcp").unwrap(), ReadyProbe::Tcp));
+        assert!(matches!(parse_probe("http").unwrap(), ReadyProbe::Http(p) if p == "/"));
+        assert!(matches!(
+            parse_probe("http:/healthz").unwrap(),
+            ReadyProbe::Http(p) if p == "/healthz"
+        ));
+        assert!(matches!(parse_probe("log:ready in \\d+ms").unwrap(), ReadyProbe::Log(_)));
+        assert!(parse_probe("http:healthz").is_err());
+        assert!(parse_probe("log:(").is_err());
+        assert!(parse_probe("udp").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_status() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0u8; 1024];
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(buf[..n].starts_with(b"GET /healthz HTTP/1.1\r\n"));
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+        });
+        let backend = BackendKey {
+            port,
+            ..Default::default()
+        };
+        assert_eq!(http_status(&backend, "/healthz").await, Some(204));
+    }
+
+    #[tokio::test]
+    async fn test_log_probe_ignores_colours() {
+        let matched = Arc::new(Notify::new());
+        let output: &[u8] = b"compiling\n\x1b[32mready\x1b[0m on port 3000\n";
+        let probe = parse_probe("log:^ready on port \\d+$").unwrap();
+        let sink = Arc::new(AppOutput::new("web", None));
+        forward_output(output, false, sink, probe.pattern().cloned(), matched.clone()).await;
+
+        let backend = BackendKey::default();
+        tokio::time::timeout(Duration::from_secs(1), probe.wait(&backend, &matched))
+            .await
+            .expect("log line should have matched");
+    }
+}
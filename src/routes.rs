@@ -5,11 +5,15 @@ use std::thread;
 use std::time::{Duration, SystemTime};
 
 use crate::types::Route;
+use crate::utils::wildcard_label;
 
 const LOCK_MAX_RETRIES: u32 = 20;
 const LOCK_RETRY_DELAY_MS: u64 = 50;
 const STALE_LOCK_THRESHOLD_MS: u64 = 10_000;
 
+/// File name of the route table inside the state dir.
+pub const ROUTES_FILE: &str = "routes.json";
+
 pub struct RouteStore {
     state_dir: PathBuf,
 }
@@ -20,15 +24,15 @@ impl RouteStore {
         Ok(Self { state_dir })
     }
 
-    fn routes_path(&self) -> PathBuf {
-        self.state_dir.join("routes.json")
+    pub fn routes_path(&self) -> PathBuf {
+        self.state_dir.join(ROUTES_FILE)
     }
 
     fn lock_path(&self) -> PathBuf {
         self.state_dir.join("routes.lock")
     }
 
-    /// Load routes from disk, filtering out stale entries (dead PIDs).
+    /// Load routes from disk, filtering out stale entries (dead PIDs; aliases are kept).
     /// When `persist_cleanup` is true and stale entries were removed,
     /// the cleaned list is written back to disk (only safe while holding the lock).
     pub fn load(&self, persist_cleanup: bool) -> Result<Vec<Route>> {
@@ -46,7 +50,7 @@ impl RouteStore {
         };
         let alive: Vec<Route> = routes
             .into_iter()
-            .filter(|r| is_pid_alive(r.pid))
+            .filter(Route::is_alive)
             .collect();
 
         if persist_cleanup {
@@ -70,9 +74,14 @@ impl RouteStore {
         Ok(serde_json::from_str(&content).unwrap_or_default())
     }
 
+    /// Write routes atomically (temp file + rename) so watchers never see a partial file.
     pub fn save(&self, routes: &[Route]) -> Result<()> {
         let content = serde_json::to_string_pretty(routes)?;
-        fs::write(self.routes_path(), content)?;
+        let tmp_path = self
+            .state_dir
+            .join(format!("{}.{}.tmp", ROUTES_FILE, std::process::id()));
+        fs::write(&tmp_path, content)?;
+        fs::rename(&tmp_path, self.routes_path())?;
         Ok(())
     }
 
@@ -106,7 +115,12 @@ impl RouteStore {
         self.acquire_lock()?;
         let result = (|| {
             let mut routes = self.load(true)?;
-            routes.retain(|r| r.hostname != route.hostname);
+            // Instances that joined (or that a new instance joins) stay; only
+            // their own process can replace or remove them.
+            routes.retain(|r| {
+                !r.is_mounted_at(&route.hostname, route.path_prefix.as_deref())
+                    || (r.pid != route.pid && (route.join || r.join))
+            });
             routes.push(route);
             self.save(&routes)
         })();
@@ -114,11 +128,16 @@ impl RouteStore {
         result
     }
 
-    pub fn remove(&self, hostname: &str) -> Result<()> {
+    /// Remove the route for `hostname` at `path_prefix`, leaving the
+    /// hostname's other paths in place. With `pid`, only that process's
+    /// instance is removed.
+    pub fn remove(&self, hostname: &str, path_prefix: Option<&str>, pid: Option<u32>) -> Result<()> {
         self.acquire_lock()?;
         let result = (|| {
             let mut routes = self.load(true)?;
-            routes.retain(|r| r.hostname != hostname);
+            routes.retain(|r| {
+                !r.is_mounted_at(hostname, path_prefix) || pid.is_some_and(|pid| r.pid != pid)
+            });
             self.save(&routes)
         })();
         self.release_lock();
@@ -126,7 +145,34 @@ impl RouteStore {
     }
 }
 
-fn is_pid_alive(pid: u32) -> bool {
+/// The route serving `path` on `hostname`: the one with the longest matching
+/// path prefix, falling back to the hostname's route without a prefix. Routes
+/// for exactly `hostname` win over wildcards, and of those the most specific
+/// (e.g. `*.eu.myapp.localhost` over `*.myapp.localhost`) wins.
+pub fn find_route<'a>(routes: &'a [Route], hostname: &str, path: &str) -> Option<&'a Route> {
+    let prefix_len = |r: &Route| r.path_prefix.as_ref().map_or(0, |p| p.len());
+    routes
+        .iter()
+        .filter(|r| r.hostname == hostname && r.matches_path(path))
+        .max_by_key(|r| prefix_len(r))
+        .or_else(|| {
+            routes
+                .iter()
+                .filter(|r| wildcard_label(&r.hostname, hostname).is_some() && r.matches_path(path))
+                .max_by_key(|r| (r.hostname.len(), prefix_len(r)))
+        })
+}
+
+/// Every live instance registered at the same hostname and path as `route`.
+pub fn instances<'a>(routes: &'a [Route], route: &Route) -> Vec<&'a Route> {
+    routes
+        .iter()
+        .filter(|r| r.is_mounted_at(&route.hostname, route.path_prefix.as_deref()))
+        .filter(|r| r.is_alive())
+        .collect()
+}
+
+pub fn is_pid_alive(pid: u32) -> bool {
     use nix::sys::signal;
     use nix::unistd::Pid;
     signal::kill(Pid::from_raw(pid as i32), None).is_ok()
@@ -187,11 +233,13 @@ mod tests {
                 hostname: "test1.localhost".to_string(),
                 port: 4000,
                 pid: std::process::id(),
+                ..Default::default()
             },
             Route {
                 hostname: "test2.localhost".to_string(),
                 port: 4001,
                 pid: std::process::id(),
+                ..Default::default()
             },
         ];
 
@@ -211,6 +259,7 @@ mod tests {
             hostname: "newapp.localhost".to_string(),
             port: 4200,
             pid: std::process::id(),
+            ..Default::default()
         };
 
         store.add(route.clone()).unwrap();
@@ -229,12 +278,14 @@ mod tests {
             hostname: "test.localhost".to_string(),
             port: 4000,
             pid: std::process::id(),
+            ..Default::default()
         };
 
         let route2 = Route {
             hostname: "test.localhost".to_string(),
             port: 4100,
             pid: std::process::id(),
+            ..Default::default()
         };
 
         store.add(route1).unwrap();
@@ -245,6 +296,73 @@ mod tests {
         assert_eq!(routes[0].port, 4100); // Second route should replace first
     }
 
+    #[test]
+    fn test_joined_instances() {
+        let (store, _temp) = create_test_store();
+        // Two live processes: the test runner and its parent
+        let (me, parent) = (std::process::id(), std::os::unix::process::parent_id());
+        let instance = |pid, port, join| Route {
+            hostname: "api.localhost".to_string(),
+            port,
+            pid,
+            join,
+            ..Default::default()
+        };
+
+        store.add(instance(parent, 4000, false)).unwrap();
+        store.add(instance(me, 4100, true)).unwrap();
+        // Re-registering the first instance (e.g. once ready) keeps the joined one
+        store.add(instance(parent, 4000, false)).unwrap();
+        let routes = store.load(false).unwrap();
+        assert_eq!(routes.len(), 2);
+        assert_eq!(instances(&routes, &routes[0]).len(), 2);
+
+        store.remove("api.localhost", None, Some(me)).unwrap();
+        let routes = store.load(false).unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].port, 4000);
+    }
+
+    #[test]
+    fn test_aliases_outlive_processes() {
+        let (store, _temp) = create_test_store();
+        store
+            .save(&[
+                Route {
+                    hostname: "db-admin.localhost".to_string(),
+                    port: 5050,
+                    alias: true,
+                    ..Default::default()
+                },
+                Route {
+                    hostname: "db-admin.localhost".to_string(),
+                    port: 4000,
+                    pid: 999999,
+                    ..Default::default()
+                },
+            ])
+            .unwrap();
+
+        let routes = store.load(true).unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].port, 5050);
+
+        // Removing PID 0 only ever removes the alias
+        store
+            .add(Route {
+                hostname: "db-admin.localhost".to_string(),
+                port: 4100,
+                pid: std::process::id(),
+                join: true,
+                ..Default::default()
+            })
+            .unwrap();
+        store.remove("db-admin.localhost", None, Some(0)).unwrap();
+        let routes = store.load(false).unwrap();
+        assert_eq!(routes.len(), 1);
+        assert_eq!(routes[0].port, 4100);
+    }
+
     #[test]
     fn test_remove_route() {
         let (store, _temp) = create_test_store();
@@ -253,18 +371,20 @@ mod tests {
             hostname: "app1.localhost".to_string(),
             port: 4000,
             pid: std::process::id(),
+            ..Default::default()
         };
 
         let route2 = Route {
             hostname: "app2.localhost".to_string(),
             port: 4001,
             pid: std::process::id(),
+            ..Default::default()
         };
 
         store.add(route1).unwrap();
         store.add(route2).unwrap();
 
-        store.remove("app1.localhost").unwrap();
+        store.remove("app1.localhost", None, None).unwrap();
 
         let routes = store.load(false).unwrap();
         assert_eq!(routes.len(), 1);
@@ -275,7 +395,7 @@ mod tests {
     fn test_remove_nonexistent() {
         let (store, _temp) = create_test_store();
 
-        let result = store.remove("nonexistent.localhost");
+        let result = store.remove("nonexistent.localhost", None, None);
         assert!(result.is_ok());
 
         let routes = store.load(false).unwrap();
@@ -292,11 +412,13 @@ mod tests {
                 hostname: "alive.localhost".to_string(),
                 port: 4000,
                 pid: std::process::id(), // Current process (alive)
+                ..Default::default()
             },
             Route {
                 hostname: "dead.localhost".to_string(),
                 port: 4001,
                 pid: 999999, // Non-existent PID
+                ..Default::default()
             },
         ];
 
@@ -317,11 +439,13 @@ mod tests {
                 hostname: "alive.localhost".to_string(),
                 port: 4000,
                 pid: std::process::id(),
+                ..Default::default()
             },
             Route {
                 hostname: "dead.localhost".to_string(),
                 port: 4001,
                 pid: 999999,
+                ..Default::default()
             },
         ];
 
@@ -332,6 +456,25 @@ mod tests {
         assert_eq!(loaded.len(), 2);
     }
 
+    #[test]
+    fn test_save_leaves_no_temp_files() {
+        let (store, temp) = create_test_store();
+        store
+            .save(&[Route {
+                hostname: "app.localhost".to_string(),
+                port: 4000,
+                pid: std::process::id(),
+                ..Default::default()
+            }])
+            .unwrap();
+
+        let names: Vec<_> = fs::read_dir(temp.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, vec![std::ffi::OsString::from(ROUTES_FILE)]);
+    }
+
     #[test]
     fn test_load_invalid_json() {
         let (store, temp) = create_test_store();
@@ -387,6 +530,7 @@ mod tests {
                     hostname: format!("app{}.localhost", i),
                     port: 4000 + i as u16,
                     pid: std::process::id(),
+                    ..Default::default()
                 };
                 store_clone.add(route).unwrap();
             });
@@ -0,0 +1,210 @@
+use hyper::header::{ACCEPT, HeaderMap};
+use hyper::Method;
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::time::{Duration, Instant};
+
+use crate::pool::{self, BackendKey};
+use crate::types::{Route, RouteState};
+use crate::watcher::RouteCache;
+
+/// How long requests are held for a freshly registered app unless its route
+/// says otherwise (`portless --startup-timeout`).
+pub const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// Pause between connection attempts while a backend boots.
+const STARTUP_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Remembers which backends have accepted a connection since they were
+/// registered. Until then, requests to them are held instead of failing.
+pub struct StartupTracker {
+    up: Mutex<HashSet<(String, String)>>,
+}
+
+impl StartupTracker {
+    pub fn new() -> Self {
+        Self {
+            up: Mutex::new(HashSet::new()),
+        }
+    }
+
+    /// When to give up waiting for `route`'s backend, or `None` if it has
+    /// already been reached or its startup window is over.
+    pub fn deadline(&self, route: &Route) -> Option<Instant> {
+        let started_at = route.started_at?;
+        if self.is_up(route) {
+            return None;
+        }
+        let window = route
+            .startup_timeout_secs
+            .map_or(STARTUP_TIMEOUT, Duration::from_secs);
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH + Duration::from_secs(started_at))
+            .unwrap_or_default();
+        window
+            .checked_sub(elapsed)
+            .filter(|left| !left.is_zero())
+            .map(|left| Instant::now() + left)
+    }
+
+    pub fn mark_up(&self, route: &Route) {
+        if let Ok(mut up) = self.up.lock() {
+            up.insert((route.hostname.clone(), route.target()));
+        }
+    }
+
+    /// Forget backends that no route points at anymore, or whose route is
+    /// starting again, e.g. after `portless --restart` brought a crashed app back.
+    pub fn retain(&self, routes: &[Route]) {
+        if let Ok(mut up) = self.up.lock() {
+            up.retain(|(host, target)| {
+                routes.iter().any(|r| {
+                    &r.hostname == host && &r.target() == target && r.state != RouteState::Starting
+                })
+            });
+        }
+    }
+
+    fn is_up(&self, route: &Route) -> bool {
+        self.up
+            .lock()
+            .map(|up| up.contains(&(route.hostname.clone(), route.target())))
+            .unwrap_or(false)
+    }
+}
+
+/// Whether anything is listening at `route`'s port or socket yet.
+pub async fn backend_accepts(route: &Route) -> bool {
+    pool::connect(&BackendKey::for_route(route)).await.is_ok()
+}
+
+/// Wait until `route` is no longer reported as starting and its backend
+/// accepts connections, or until `deadline` passes.
+pub async fn wait_for_backend(routes: &RouteCache, route: &Route, deadline: Instant) -> bool {
+    let mount = route.path_prefix.as_deref();
+    loop {
+        let starting = routes
+            .snapshot()
+            .iter()
+            .any(|r| r.is_mounted_at(&route.hostname, mount) && r.state == RouteState::Starting);
+        if !starting && backend_accepts(route).await {
+            return true;
+        }
+        if Instant::now() + STARTUP_RETRY_INTERVAL >= deadline {
+            return false;
+        }
+        tokio::time::sleep(STARTUP_RETRY_INTERVAL).await;
+    }
+}
+
+/// A browser loading a page, which is better served a page that reloads
+/// itself than a request that hangs.
+pub fn is_navigation(method: &Method, headers: &HeaderMap) -> bool {
+    if let Some(mode) = headers.get("sec-fetch-mode") {
+        return mode == "navigate";
+    }
+    method == Method::GET
+        && headers
+            .get(ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|accept| accept.contains("text/html"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::RouteStore;
+    use hyper::header::HeaderValue;
+    use tempfile::TempDir;
+
+    fn route(started_secs_ago: u64, timeout: Option<u64>) -> Route {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        Route {
+            hostname: "app.localhost".to_string(),
+            port: 4000,
+            pid: 1,
+            started_at: Some(now - started_secs_ago),
+            startup_timeout_secs: timeout,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_deadline_within_startup_window() {
+        let tracker = StartupTracker::new();
+        assert!(tracker.deadline(&route(0, None)).is_some());
+        assert!(tracker.deadline(&route(60, None)).is_none());
+        assert!(tracker.deadline(&route(60, Some(120))).is_some());
+        assert!(tracker.deadline(&route(0, Some(0))).is_none());
+
+        let fresh = route(0, None);
+        tracker.mark_up(&fresh);
+        assert!(tracker.deadline(&fresh).is_none());
+        tracker.retain(&[]);
+        assert!(tracker.deadline(&fresh).is_some());
+
+        // A restarted app is held for again until it is back.
+        tracker.mark_up(&fresh);
+        tracker.retain(std::slice::from_ref(&fresh));
+        assert!(tracker.deadline(&fresh).is_none());
+        let restarting = Route {
+            state: RouteState::Starting,
+            ..fresh
+        };
+        tracker.retain(std::slice::from_ref(&restarting));
+        assert!(tracker.deadline(&restarting).is_some());
+    }
+
+    #[test]
+    fn test_routes_without_start_time_are_not_held() {
+        let route = Route {
+            started_at: None,
+            ..route(0, None)
+        };
+        assert!(StartupTracker::new().deadline(&route).is_none());
+    }
+
+    #[test]
+    fn test_is_navigation() {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static("text/html,application/xhtml+xml"));
+        assert!(is_navigation(&Method::GET, &headers));
+        assert!(!is_navigation(&Method::POST, &headers));
+
+        headers.insert("sec-fetch-mode", HeaderValue::from_static("cors"));
+        assert!(!is_navigation(&Method::GET, &headers));
+        assert!(!is_navigation(&Method::GET, &HeaderMap::new()));
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_backend() {
+        let temp = TempDir::new().unwrap();
+        let store = RouteStore::new(temp.path().to_path_buf()).unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut route = Route {
+            hostname: "app.localhost".to_string(),
+            port,
+            pid: std::process::id(),
+            state: RouteState::Starting,
+            ..Default::default()
+        };
+        store.add(route.clone()).unwrap();
+        let cache = RouteCache::new(RouteStore::new(temp.path().to_path_buf()).unwrap());
+
+        // Listening, but the app has not passed its readiness probe yet
+        let soon = Instant::now() + Duration::from_millis(250);
+        assert!(!wait_for_backend(&cache, &route, soon).await);
+
+        route.state = RouteState::Ready;
+        store.add(route.clone()).unwrap();
+        cache.refresh();
+        assert!(wait_for_backend(&cache, &route, Instant::now()).await);
+
+        drop(listener);
+        let started = Instant::now();
+        let soon = started + Duration::from_millis(250);
+        assert!(!wait_for_backend(&cache, &route, soon).await);
+        assert!(started.elapsed() >= Duration::from_millis(150));
+    }
+}
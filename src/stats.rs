@@ -0,0 +1,177 @@
+use hyper::StatusCode;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use tokio::time::Instant;
+
+/// Length of the sliding window behind the "recent" request and error counts.
+pub const STATS_WINDOW_SECS: u64 = 60;
+
+/// Requests counted within one second.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    second: u64,
+    requests: u64,
+    errors: u64,
+}
+
+#[derive(Debug, Default)]
+struct HostStats {
+    requests: u64,
+    errors: u64,
+    buckets: VecDeque<Bucket>,
+}
+
+impl HostStats {
+    fn expire(&mut self, now: u64) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|b| now.saturating_sub(b.second) >= STATS_WINDOW_SECS)
+        {
+            self.buckets.pop_front();
+        }
+    }
+}
+
+/// Request counters for one hostname.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct StatsSnapshot {
+    pub requests: u64,
+    pub errors: u64,
+    /// Requests within the last [`STATS_WINDOW_SECS`].
+    pub recent_requests: u64,
+    pub recent_errors: u64,
+}
+
+impl StatsSnapshot {
+    /// Share of recent requests that failed, between 0 and 1.
+    pub fn recent_error_rate(&self) -> f64 {
+        if self.recent_requests == 0 {
+            0.0
+        } else {
+            self.recent_errors as f64 / self.recent_requests as f64
+        }
+    }
+}
+
+/// Per-hostname request statistics, kept in memory by the proxy.
+/// Responses with a 5xx status count as errors.
+pub struct Stats {
+    origin: Instant,
+    hosts: Mutex<HashMap<String, HostStats>>,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            origin: Instant::now(),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record(&self, hostname: &str, status: StatusCode) {
+        self.record_at(hostname, status, self.now());
+    }
+
+    pub fn snapshot(&self, hostname: &str) -> StatsSnapshot {
+        self.snapshot_at(hostname, self.now())
+    }
+
+    /// Forget hostnames for which `is_live` returns false.
+    pub fn retain(&self, is_live: impl Fn(&str) -> bool) {
+        if let Ok(mut hosts) = self.hosts.lock() {
+            hosts.retain(|host, _| is_live(host));
+        }
+    }
+
+    fn now(&self) -> u64 {
+        self.origin.elapsed().as_secs()
+    }
+
+    fn record_at(&self, hostname: &str, status: StatusCode, now: u64) {
+        let Ok(mut hosts) = self.hosts.lock() else {
+            return;
+        };
+        let stats = hosts.entry(hostname.to_string()).or_default();
+        let error = u64::from(status.is_server_error());
+        stats.requests += 1;
+        stats.errors += error;
+
+        stats.expire(now);
+        match stats.buckets.back_mut() {
+            Some(bucket) if bucket.second == now => {
+                bucket.requests += 1;
+                bucket.errors += error;
+            }
+            _ => stats.buckets.push_back(Bucket {
+                second: now,
+                requests: 1,
+                errors: error,
+            }),
+        }
+    }
+
+    fn snapshot_at(&self, hostname: &str, now: u64) -> StatsSnapshot {
+        let Ok(mut hosts) = self.hosts.lock() else {
+            return StatsSnapshot::default();
+        };
+        let Some(stats) = hosts.get_mut(hostname) else {
+            return StatsSnapshot::default();
+        };
+        stats.expire(now);
+        StatsSnapshot {
+            requests: stats.requests,
+            errors: stats.errors,
+            recent_requests: stats.buckets.iter().map(|b| b.requests).sum(),
+            recent_errors: stats.buckets.iter().map(|b| b.errors).sum(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_requests_and_errors() {
+        let stats = Stats::new();
+        stats.record_at("app.localhost", StatusCode::OK, 0);
+        stats.record_at("app.localhost", StatusCode::NOT_FOUND, 0);
+        stats.record_at("app.localhost", StatusCode::BAD_GATEWAY, 1);
+
+        let snap = stats.snapshot_at("app.localhost", 1);
+        assert_eq!(snap.requests, 3);
+        assert_eq!(snap.errors, 1);
+        assert_eq!(snap.recent_requests, 3);
+        assert_eq!(snap.recent_errors, 1);
+        assert!((snap.recent_error_rate() - 1.0 / 3.0).abs() < f64::EPSILON);
+
+        assert_eq!(stats.snapshot_at("other.localhost", 1), StatsSnapshot::default());
+    }
+
+    #[test]
+    fn test_window_expires_old_requests() {
+        let stats = Stats::new();
+        stats.record_at("app.localhost", StatusCode::INTERNAL_SERVER_ERROR, 0);
+        stats.record_at("app.localhost", StatusCode::OK, 30);
+
+        let snap = stats.snapshot_at("app.localhost", STATS_WINDOW_SECS);
+        assert_eq!(snap.requests, 2);
+        assert_eq!(snap.errors, 1);
+        assert_eq!(snap.recent_requests, 1);
+        assert_eq!(snap.recent_errors, 0);
+        assert_eq!(snap.recent_error_rate(), 0.0);
+    }
+
+    #[test]
+    fn test_retain_drops_removed_hosts() {
+        let stats = Stats::new();
+        stats.record_at("a.localhost", StatusCode::OK, 0);
+        stats.record_at("b.localhost", StatusCode::OK, 0);
+        stats.retain(|host| host == "a.localhost");
+
+        assert_eq!(stats.snapshot_at("a.localhost", 0).requests, 1);
+        assert_eq!(stats.snapshot_at("b.localhost", 0).requests, 0);
+    }
+}
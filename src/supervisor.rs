@@ -0,0 +1,98 @@
+use std::os::unix::process::ExitStatusExt;
+use std::process::ExitStatus;
+use std::time::Duration;
+
+/// Pause before the first restart of a crashed app; it doubles with each
+/// crash in a row, up to [`MAX_RESTART_DELAY`].
+pub const RESTART_DELAY: Duration = Duration::from_secs(1);
+pub const MAX_RESTART_DELAY: Duration = Duration::from_secs(30);
+/// An app that ran this long before exiting was not crash-looping, and the
+/// next restart starts over at [`RESTART_DELAY`].
+pub const STABLE_AFTER: Duration = Duration::from_secs(10);
+/// Crashes in a row, each soon after starting, before `portless` gives up.
+pub const CRASH_LOOP_LIMIT: u32 = 10;
+
+/// When `portless <name>` starts the app again after it exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RestartPolicy {
+    /// When it exits with an error or is killed by a signal
+    OnFailure,
+    /// Whenever it exits
+    Always,
+}
+
+impl RestartPolicy {
+    pub fn applies(self, status: &ExitStatus) -> bool {
+        match self {
+            RestartPolicy::OnFailure => !status.success(),
+            RestartPolicy::Always => true,
+        }
+    }
+}
+
+/// Spaces out the restarts of an app that keeps exiting.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    crashes: u32,
+}
+
+impl Backoff {
+    /// How long to wait before restarting an app that ran for `ran_for`, or
+    /// `None` once it has crashed [`CRASH_LOOP_LIMIT`] times in a row.
+    pub fn next(&mut self, ran_for: Duration) -> Option<Duration> {
+        if ran_for >= STABLE_AFTER {
+            self.crashes = 0;
+        }
+        if self.crashes >= CRASH_LOOP_LIMIT {
+            return None;
+        }
+        let delay = RESTART_DELAY
+            .saturating_mul(1 << self.crashes.min(16))
+            .min(MAX_RESTART_DELAY);
+        self.crashes += 1;
+        Some(delay)
+    }
+}
+
+/// How the app exited, e.g. `exited with code 1`.
+pub fn describe_exit(status: &ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exited with code {}", code),
+        (None, Some(signal)) => format!("was killed by signal {}", signal),
+        (None, None) => "exited".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_policy() {
+        let ok = ExitStatus::from_raw(0);
+        let failed = ExitStatus::from_raw(1 << 8);
+        let killed = ExitStatus::from_raw(9);
+        assert!(!RestartPolicy::OnFailure.applies(&ok));
+        assert!(RestartPolicy::OnFailure.applies(&failed));
+        assert!(RestartPolicy::OnFailure.applies(&killed));
+        assert!(RestartPolicy::Always.applies(&ok));
+
+        assert_eq!(describe_exit(&failed), "exited with code 1");
+        assert_eq!(describe_exit(&killed), "was killed by signal 9");
+    }
+
+    #[test]
+    fn test_backoff_doubles_and_gives_up() {
+        let mut backoff = Backoff::default();
+        let quick = Duration::from_millis(200);
+        let delays: Vec<u64> = (0..CRASH_LOOP_LIMIT)
+            .map(|_| backoff.next(quick).unwrap().as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30, 30, 30, 30]);
+        assert_eq!(backoff.next(quick), None);
+
+        // Running for a while shows the app recovered; start over.
+        assert_eq!(backoff.next(STABLE_AFTER), Some(RESTART_DELAY));
+        assert_eq!(backoff.next(quick), Some(RESTART_DELAY * 2));
+    }
+}
@@ -0,0 +1,331 @@
+use anyhow::{anyhow, Result};
+use rcgen::{
+    BasicConstraints, CertificateParams, DnType, ExtendedKeyUsagePurpose, GeneralSubtree, IsCa,
+    KeyPair, KeyUsagePurpose, NameConstraints,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use time::{Duration, OffsetDateTime};
+use tokio_rustls::rustls;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::server::{ClientHello, ResolvesServerCert};
+use tokio_rustls::rustls::sign::CertifiedKey;
+
+/// Common name of the generated root certificate.
+pub const CA_COMMON_NAME: &str = "portless Local CA";
+/// Hostname used for the leaf certificate when the client sends no SNI.
+const DEFAULT_SNI: &str = "localhost";
+/// Root CA lifetime. Regenerate with `rm -r <state_dir>/ca` (and re-trust it).
+const CA_VALIDITY_DAYS: i64 = 3650;
+/// Leaf lifetime; kept under the 398-day limit browsers enforce.
+const LEAF_VALIDITY_DAYS: i64 = 365;
+
+pub fn ca_dir(state_dir: &Path) -> PathBuf {
+    state_dir.join("ca")
+}
+
+pub fn ca_cert_path(state_dir: &Path) -> PathBuf {
+    ca_dir(state_dir).join("rootCA.pem")
+}
+
+fn ca_key_path(state_dir: &Path) -> PathBuf {
+    ca_dir(state_dir).join("rootCA-key.pem")
+}
+
+/// The local root certificate authority that signs per-hostname leaf certificates.
+///
+/// The CA is name-constrained to `localhost`, so trusting it cannot be abused to
+/// intercept traffic for real domains.
+pub struct LocalCa {
+    issuer: rcgen::Certificate,
+    key: KeyPair,
+    cert_pem: String,
+    cert_der: CertificateDer<'static>,
+}
+
+impl LocalCa {
+    /// Load the CA from `<state_dir>/ca`, generating it on first use.
+    pub fn load_or_create(state_dir: &Path) -> Result<Self> {
+        let cert_path = ca_cert_path(state_dir);
+        let key_path = ca_key_path(state_dir);
+
+        if cert_path.exists() && key_path.exists() {
+            let cert_pem = fs::read_to_string(&cert_path)?;
+            let key = KeyPair::from_pem(&fs::read_to_string(&key_path)?)
+                .map_err(|e| anyhow!("Invalid CA key {}: {}", key_path.display(), e))?;
+            // Leaf signing only needs the issuer's name and key, so rebuilding the
+            // params with the stored key chains correctly to the on-disk root.
+            let issuer = ca_params()?.self_signed(&key)?;
+            let cert_der = CertificateDer::from_pem_slice(cert_pem.as_bytes())
+                .map_err(|e| anyhow!("Invalid CA certificate {}: {}", cert_path.display(), e))?;
+            return Ok(Self {
+                issuer,
+                key,
+                cert_pem,
+                cert_der,
+            });
+        }
+
+        fs::create_dir_all(ca_dir(state_dir))?;
+        let key = KeyPair::generate()?;
+        let issuer = ca_params()?.self_signed(&key)?;
+        let cert_pem = issuer.pem();
+        let cert_der = issuer.der().clone();
+
+        fs::write(&cert_path, &cert_pem)?;
+        write_private(&key_path, &key.serialize_pem())?;
+
+        Ok(Self {
+            issuer,
+            key,
+            cert_pem,
+            cert_der,
+        })
+    }
+
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    pub fn cert_der(&self) -> &[u8] {
+        &self.cert_der
+    }
+
+    /// Mint a leaf certificate for `hostname` signed by this CA.
+    pub fn issue(&self, hostname: &str) -> Result<CertifiedKey> {
+        let leaf_key = KeyPair::generate()?;
+        let mut params = CertificateParams::new(vec![hostname.to_string()])?;
+        params.distinguished_name.push(DnType::CommonName, hostname);
+        params.key_usages = vec![
+            KeyUsagePurpose::DigitalSignature,
+            KeyUsagePurpose::KeyEncipherment,
+        ];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.use_authority_key_identifier_extension = true;
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - Duration::days(1);
+        params.not_after = now + Duration::days(LEAF_VALIDITY_DAYS);
+
+        let cert = params.signed_by(&leaf_key, &self.issuer, &self.key)?;
+        let key_der = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(leaf_key.serialize_der()));
+        let signing_key = rustls::crypto::ring::sign::any_supported_type(&key_der)?;
+
+        Ok(CertifiedKey::new(
+            vec![cert.der().clone(), self.cert_der.clone()],
+            signing_key,
+        ))
+    }
+}
+
+fn ca_params() -> Result<CertificateParams> {
+    let mut params = CertificateParams::new(Vec::<String>::new())?;
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params.distinguished_name.push(DnType::CommonName, CA_COMMON_NAME);
+    params.distinguished_name.push(DnType::OrganizationName, "portless");
+    params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+    params.key_usages = vec![
+        KeyUsagePurpose::KeyCertSign,
+        KeyUsagePurpose::CrlSign,
+        KeyUsagePurpose::DigitalSignature,
+    ];
+    params.name_constraints = Some(NameConstraints {
+        permitted_subtrees: vec![GeneralSubtree::DnsName("localhost".to_string())],
+        excluded_subtrees: vec![],
+    });
+    let now = OffsetDateTime::now_utc();
+    params.not_before = now - Duration::days(1);
+    params.not_after = now + Duration::days(CA_VALIDITY_DAYS);
+    Ok(params)
+}
+
+fn write_private(path: &Path, content: &str) -> Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+/// SNI-driven certificate resolver: mints and caches a leaf per `.localhost` hostname.
+pub struct CertResolver {
+    ca: LocalCa,
+    cache: Mutex<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl CertResolver {
+    pub fn new(ca: LocalCa) -> Self {
+        Self {
+            ca,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn certificate_for(&self, hostname: &str) -> Option<Arc<CertifiedKey>> {
+        let mut cache = self.cache.lock().ok()?;
+        if let Some(key) = cache.get(hostname) {
+            return Some(key.clone());
+        }
+        match self.ca.issue(hostname) {
+            Ok(key) => {
+                let key = Arc::new(key);
+                cache.insert(hostname.to_string(), key.clone());
+                Some(key)
+            }
+            Err(e) => {
+                eprintln!("failed to issue certificate for {}: {}", hostname, e);
+                None
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for CertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertResolver").finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let hostname = client_hello
+            .server_name()
+            .map(|s| s.to_ascii_lowercase())
+            .unwrap_or_else(|| DEFAULT_SNI.to_string());
+        // Only mint for names the CA is constrained to; anything else is refused.
+        if hostname != DEFAULT_SNI && !hostname.ends_with(".localhost") {
+            return None;
+        }
+        self.certificate_for(&hostname)
+    }
+}
+
+/// Build the rustls server config used by the proxy in `--https` mode.
+pub fn server_config(state_dir: &Path) -> Result<Arc<rustls::ServerConfig>> {
+    let ca = LocalCa::load_or_create(state_dir)?;
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(CertResolver::new(ca)));
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
+/// CA bundles of common systems, tried in order when `SSL_CERT_FILE` is unset.
+const SYSTEM_CA_BUNDLES: &[&str] = &[
+    "/etc/ssl/certs/ca-certificates.crt",
+    "/etc/pki/tls/certs/ca-bundle.crt",
+    "/etc/ssl/ca-bundle.pem",
+    "/etc/ssl/cert.pem",
+];
+
+/// Root certificates for verifying `https://` upstreams: those in the PEM file
+/// named by `SSL_CERT_FILE`, or else the system's CA bundle.
+pub fn system_roots() -> rustls::RootCertStore {
+    let mut roots = rustls::RootCertStore::empty();
+    let bundle = std::env::var_os("SSL_CERT_FILE")
+        .map(PathBuf::from)
+        .or_else(|| SYSTEM_CA_BUNDLES.iter().map(PathBuf::from).find(|p| p.exists()));
+    if let Some(certs) = bundle.and_then(|path| CertificateDer::pem_file_iter(path).ok()) {
+        roots.add_parsable_certificates(certs.flatten());
+    }
+    roots
+}
+
+/// Build the rustls client config the proxy uses towards `https://` upstreams.
+pub fn client_config(roots: rustls::RootCertStore) -> Arc<rustls::ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .expect("ring supports the default protocol versions")
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    Arc::new(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    #[test]
+    fn test_ca_created_and_reused() {
+        let temp = TempDir::new().unwrap();
+        let ca1 = LocalCa::load_or_create(temp.path()).unwrap();
+        assert!(ca_cert_path(temp.path()).exists());
+        assert!(ca_key_path(temp.path()).exists());
+
+        let ca2 = LocalCa::load_or_create(temp.path()).unwrap();
+        assert_eq!(ca1.cert_der, ca2.cert_der);
+    }
+
+    #[test]
+    fn test_ca_key_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp = TempDir::new().unwrap();
+        LocalCa::load_or_create(temp.path()).unwrap();
+        let mode = fs::metadata(ca_key_path(temp.path()))
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_leaf_chains_to_reloaded_ca() {
+        let temp = TempDir::new().unwrap();
+        let der = LocalCa::load_or_create(temp.path()).unwrap().cert_der;
+        // Reload so the server signs with the reconstructed issuer.
+        let server_config = server_config(temp.path()).unwrap();
+
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(der).unwrap();
+        let client_config = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+        let (client_io, server_io) = tokio::io::duplex(16 * 1024);
+        let acceptor = TlsAcceptor::from(server_config);
+        let server = tokio::spawn(async move {
+            let mut tls = acceptor.accept(server_io).await.unwrap();
+            tls.write_all(b"ok").await.unwrap();
+            tls.shutdown().await.unwrap();
+        });
+
+        let connector = TlsConnector::from(Arc::new(client_config));
+        let name = ServerName::try_from("myapp.localhost").unwrap();
+        let mut tls = connector.connect(name, client_io).await.unwrap();
+        let mut buf = Vec::new();
+        tls.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"ok");
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_resolver_caches_per_hostname() {
+        let temp = TempDir::new().unwrap();
+        let resolver = CertResolver::new(LocalCa::load_or_create(temp.path()).unwrap());
+        let a = resolver.certificate_for("a.localhost").unwrap();
+        let a2 = resolver.certificate_for("a.localhost").unwrap();
+        let b = resolver.certificate_for("b.localhost").unwrap();
+        assert!(Arc::ptr_eq(&a, &a2));
+        assert!(!Arc::ptr_eq(&a, &b));
+    }
+}
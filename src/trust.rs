@@ -0,0 +1,377 @@
+use anyhow::{anyhow, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::tls::CA_COMMON_NAME;
+
+/// Base name of the anchor file installed into system trust stores.
+const ANCHOR_NAME: &str = "portless-ca";
+
+/// A Linux distribution family's system-wide trust anchor directory and refresh command.
+struct SystemStore {
+    anchor_dir: &'static str,
+    extension: &'static str,
+    refresh: &'static [&'static str],
+}
+
+const SYSTEM_STORES: &[SystemStore] = &[
+    // Debian, Ubuntu
+    SystemStore {
+        anchor_dir: "usr/local/share/ca-certificates",
+        extension: "crt",
+        refresh: &["update-ca-certificates"],
+    },
+    // Fedora, RHEL, CentOS
+    SystemStore {
+        anchor_dir: "etc/pki/ca-trust/source/anchors",
+        extension: "pem",
+        refresh: &["update-ca-trust", "extract"],
+    },
+    // Arch Linux
+    SystemStore {
+        anchor_dir: "etc/ca-certificates/trust-source/anchors",
+        extension: "crt",
+        refresh: &["trust", "extract-compat"],
+    },
+    // openSUSE
+    SystemStore {
+        anchor_dir: "usr/share/pki/trust/anchors",
+        extension: "pem",
+        refresh: &["update-ca-certificates"],
+    },
+];
+
+/// Profile roots (relative to $HOME) that hold Firefox NSS databases.
+const FIREFOX_PROFILE_ROOTS: &[&str] = &[
+    ".mozilla/firefox",
+    "snap/firefox/common/.mozilla/firefox",
+    ".var/app/org.mozilla.firefox/.mozilla/firefox",
+];
+
+/// Shared NSS database used by Chromium and Chrome on Linux.
+const CHROMIUM_NSSDB: &str = ".pki/nssdb";
+
+/// One command the trust/untrust operation will run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Keep going if this step fails (e.g. removing a cert that was never added).
+    pub ignore_failure: bool,
+}
+
+impl Step {
+    fn new(program: &str, args: &[&str]) -> Self {
+        Self {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            ignore_failure: false,
+        }
+    }
+
+    /// Prefix with `sudo` unless we are already root.
+    fn privileged(mut self, as_root: bool) -> Self {
+        if !as_root {
+            self.args.insert(0, self.program);
+            self.program = "sudo".to_string();
+        }
+        self
+    }
+
+    fn ignoring_failure(mut self) -> Self {
+        self.ignore_failure = true;
+        self
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            if arg.contains(' ') || arg.contains(',') {
+                write!(f, " \"{}\"", arg)?;
+            } else {
+                write!(f, " {}", arg)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The commands to run plus anything the user has to do by hand.
+#[derive(Debug, Default)]
+pub struct TrustPlan {
+    pub steps: Vec<Step>,
+    pub warnings: Vec<String>,
+}
+
+/// Where to look for trust stores. `root` is `/` outside of tests.
+pub struct TrustEnv {
+    pub root: PathBuf,
+    pub home: PathBuf,
+    pub as_root: bool,
+    pub has_certutil: bool,
+}
+
+impl TrustEnv {
+    pub fn detect() -> Self {
+        Self {
+            root: PathBuf::from("/"),
+            home: dirs::home_dir().unwrap_or_else(|| PathBuf::from("/tmp")),
+            as_root: unsafe { nix::libc::getuid() } == 0,
+            has_certutil: find_in_path("certutil").is_some(),
+        }
+    }
+}
+
+/// Plan installing `ca_pem` into the system store and every NSS database found.
+pub fn install_plan(ca_pem: &Path, env: &TrustEnv) -> TrustPlan {
+    let mut plan = TrustPlan::default();
+    let ca = ca_pem.to_string_lossy();
+
+    match SYSTEM_STORES
+        .iter()
+        .find(|s| env.root.join(s.anchor_dir).is_dir())
+    {
+        Some(store) => {
+            let anchor = anchor_path(env, store);
+            plan.steps.push(
+                Step::new("install", &["-m", "644", &ca, &anchor.to_string_lossy()])
+                    .privileged(env.as_root),
+            );
+            plan.steps
+                .push(Step::new(store.refresh[0], &store.refresh[1..]).privileged(env.as_root));
+        }
+        None => plan.warnings.push(
+            "No supported system trust store found; add the CA manually.".to_string(),
+        ),
+    }
+
+    let databases = find_nss_databases(&env.home);
+    if !databases.is_empty() && !env.has_certutil {
+        plan.warnings.push(
+            "certutil not found; install NSS tools (e.g. libnss3-tools or nss-tools) \
+             to trust the CA in Firefox/Chromium."
+                .to_string(),
+        );
+    } else {
+        for db in databases {
+            plan.steps.push(Step::new(
+                "certutil",
+                &["-A", "-d", &db, "-t", "C,,", "-n", CA_COMMON_NAME, "-i", &ca],
+            ));
+        }
+    }
+
+    plan
+}
+
+/// Plan removing the CA from every location `install_plan` may have written to.
+pub fn uninstall_plan(env: &TrustEnv) -> TrustPlan {
+    let mut plan = TrustPlan::default();
+
+    for store in SYSTEM_STORES {
+        let anchor = anchor_path(env, store);
+        if anchor.exists() {
+            plan.steps
+                .push(Step::new("rm", &["-f", &anchor.to_string_lossy()]).privileged(env.as_root));
+            plan.steps
+                .push(Step::new(store.refresh[0], &store.refresh[1..]).privileged(env.as_root));
+        }
+    }
+
+    let databases = find_nss_databases(&env.home);
+    if !databases.is_empty() && !env.has_certutil {
+        plan.warnings.push(
+            "certutil not found; remove the CA from Firefox/Chromium settings manually."
+                .to_string(),
+        );
+    } else {
+        for db in databases {
+            plan.steps.push(
+                Step::new("certutil", &["-D", "-d", &db, "-n", CA_COMMON_NAME])
+                    .ignoring_failure(),
+            );
+        }
+    }
+
+    plan
+}
+
+fn anchor_path(env: &TrustEnv, store: &SystemStore) -> PathBuf {
+    env.root
+        .join(store.anchor_dir)
+        .join(format!("{}.{}", ANCHOR_NAME, store.extension))
+}
+
+/// NSS database specs (`sql:<dir>` / `dbm:<dir>`) for Chromium and all Firefox profiles.
+pub fn find_nss_databases(home: &Path) -> Vec<String> {
+    let mut dirs = vec![home.join(CHROMIUM_NSSDB)];
+    for root in FIREFOX_PROFILE_ROOTS {
+        if let Ok(entries) = fs::read_dir(home.join(root)) {
+            let mut profiles: Vec<PathBuf> = entries
+                .filter_map(|e| e.ok())
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect();
+            profiles.sort();
+            dirs.extend(profiles);
+        }
+    }
+
+    dirs.into_iter()
+        .filter_map(|dir| {
+            if dir.join("cert9.db").exists() {
+                Some(format!("sql:{}", dir.display()))
+            } else if dir.join("cert8.db").exists() {
+                Some(format!("dbm:{}", dir.display()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Run each step in order, stopping at the first failure that isn't ignorable.
+pub fn run_steps(steps: &[Step]) -> Result<()> {
+    for step in steps {
+        let status = std::process::Command::new(&step.program)
+            .args(&step.args)
+            .status();
+        let ok = matches!(&status, Ok(s) if s.success());
+        if !ok && !step.ignore_failure {
+            return Err(match status {
+                Err(e) => anyhow!("Failed to run `{}`: {}", step, e),
+                Ok(s) => anyhow!("`{}` exited with {}", step, s),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|p| p.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn test_env(root: &TempDir, home: &TempDir, as_root: bool) -> TrustEnv {
+        TrustEnv {
+            root: root.path().to_path_buf(),
+            home: home.path().to_path_buf(),
+            as_root,
+            has_certutil: true,
+        }
+    }
+
+    #[test]
+    fn test_install_plan_debian_as_user() {
+        let root = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("usr/local/share/ca-certificates")).unwrap();
+
+        let plan = install_plan(Path::new("/ca.pem"), &test_env(&root, &home, false));
+        assert!(plan.warnings.is_empty());
+        assert_eq!(plan.steps.len(), 2);
+        assert_eq!(plan.steps[0].program, "sudo");
+        assert_eq!(plan.steps[0].args[0], "install");
+        assert!(plan.steps[0].args.last().unwrap().ends_with("portless-ca.crt"));
+        assert_eq!(plan.steps[1].to_string(), "sudo update-ca-certificates");
+    }
+
+    #[test]
+    fn test_install_plan_fedora_as_root() {
+        let root = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        fs::create_dir_all(root.path().join("etc/pki/ca-trust/source/anchors")).unwrap();
+
+        let plan = install_plan(Path::new("/ca.pem"), &test_env(&root, &home, true));
+        assert_eq!(plan.steps[0].program, "install");
+        assert_eq!(plan.steps[1].to_string(), "update-ca-trust extract");
+    }
+
+    #[test]
+    fn test_install_plan_without_system_store() {
+        let root = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let plan = install_plan(Path::new("/ca.pem"), &test_env(&root, &home, false));
+        assert!(plan.steps.is_empty());
+        assert_eq!(plan.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_find_nss_databases() {
+        let home = TempDir::new().unwrap();
+        let chromium = home.path().join(".pki/nssdb");
+        let firefox = home.path().join(".mozilla/firefox/abcd.default");
+        let legacy = home.path().join(".mozilla/firefox/efgh.old");
+        let empty = home.path().join(".mozilla/firefox/ijkl.empty");
+        for dir in [&chromium, &firefox, &legacy, &empty] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::write(chromium.join("cert9.db"), "").unwrap();
+        fs::write(firefox.join("cert9.db"), "").unwrap();
+        fs::write(legacy.join("cert8.db"), "").unwrap();
+
+        let dbs = find_nss_databases(home.path());
+        assert_eq!(
+            dbs,
+            vec![
+                format!("sql:{}", chromium.display()),
+                format!("sql:{}", firefox.display()),
+                format!("dbm:{}", legacy.display()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_certutil_missing_warns() {
+        let root = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let db = home.path().join(".pki/nssdb");
+        fs::create_dir_all(&db).unwrap();
+        fs::write(db.join("cert9.db"), "").unwrap();
+
+        let mut env = test_env(&root, &home, false);
+        env.has_certutil = false;
+        let plan = install_plan(Path::new("/ca.pem"), &env);
+        assert!(plan.steps.iter().all(|s| s.program != "certutil"));
+        assert!(plan.warnings.iter().any(|w| w.contains("certutil")));
+    }
+
+    #[test]
+    fn test_uninstall_plan_only_touches_installed_anchors() {
+        let root = TempDir::new().unwrap();
+        let home = TempDir::new().unwrap();
+        let debian = root.path().join("usr/local/share/ca-certificates");
+        fs::create_dir_all(&debian).unwrap();
+        fs::create_dir_all(root.path().join("etc/pki/ca-trust/source/anchors")).unwrap();
+        fs::write(debian.join("portless-ca.crt"), "").unwrap();
+        let db = home.path().join(".pki/nssdb");
+        fs::create_dir_all(&db).unwrap();
+        fs::write(db.join("cert9.db"), "").unwrap();
+
+        let plan = uninstall_plan(&test_env(&root, &home, true));
+        assert_eq!(plan.steps.len(), 3);
+        assert_eq!(plan.steps[0].program, "rm");
+        assert_eq!(plan.steps[1].program, "update-ca-certificates");
+        assert_eq!(plan.steps[2].program, "certutil");
+        assert!(plan.steps[2].ignore_failure);
+    }
+
+    #[test]
+    fn test_step_display_quotes_arguments() {
+        let step = Step::new("certutil", &["-n", CA_COMMON_NAME, "-t", "C,,"]);
+        assert_eq!(
+            step.to_string(),
+            "certutil -n \"portless Local CA\" -t \"C,,\""
+        );
+    }
+}
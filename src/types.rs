@@ -1,10 +1,363 @@
+use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::str::FromStr;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Route {
     pub hostname: String,
     pub port: u16,
     pub pid: u32,
+    /// Speak HTTP/2 with prior knowledge (h2c) to the backend, e.g. for gRPC servers.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub h2c: bool,
+    /// Command line the app was started with, for display.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// Registration time in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started_at: Option<u64>,
+    /// How long after `started_at` the proxy holds requests while the app
+    /// boots; the proxy's default applies when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub startup_timeout_secs: Option<u64>,
+    /// Readiness as reported by `portless <name>`. Routes written by older
+    /// versions have no state and count as ready.
+    #[serde(default, skip_serializing_if = "RouteState::is_ready")]
+    pub state: RouteState,
+    /// How the proxy checks the backend periodically; TCP when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// Only requests under this path (e.g. `/api`) go to this route; the
+    /// longest matching prefix on a hostname wins.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+    /// Remove `path_prefix` from the request path before forwarding.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub strip_prefix: bool,
+    /// Registered with `--join`: serve alongside the other instances at this
+    /// hostname and path instead of replacing them.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub join: bool,
+    /// How requests are spread over the instances; round-robin when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balance: Option<Balance>,
+    /// Created by `portless alias` for a port served by something portless did
+    /// not start. Aliases have no owning process (`pid` is 0) and persist
+    /// until `portless unalias`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub alias: bool,
+    /// Remote origin an alias forwards to instead of a local port; `port`
+    /// then holds the upstream's port.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream: Option<Upstream>,
+    /// Unix socket the app listens on instead of a port (`port` is then 0),
+    /// for apps started with `--socket`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<PathBuf>,
+    /// Arguments `portless` was given to run the app (options, name and
+    /// command) and the directory it ran in, so `portless restart` can start
+    /// it again.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+}
+
+/// Load-balancing strategy across the instances of a route.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Balance {
+    /// Take turns
+    #[default]
+    RoundRobin,
+    /// Pick the instance with the fewest requests in flight
+    LeastConn,
+}
+
+impl Route {
+    /// Whether the route is still in use: aliases always are, app routes while
+    /// their process runs.
+    pub fn is_alive(&self) -> bool {
+        self.alias || crate::routes::is_pid_alive(self.pid)
+    }
+
+    /// `hostname` plus the path prefix, e.g. `myapp.localhost/api`. Unique per route.
+    pub fn name(&self) -> String {
+        format!("{}{}", self.hostname, self.path_prefix.as_deref().unwrap_or(""))
+    }
+
+    /// Whether this route is registered for `hostname` at `path_prefix`.
+    pub fn is_mounted_at(&self, hostname: &str, path_prefix: Option<&str>) -> bool {
+        self.hostname == hostname && self.path_prefix.as_deref() == path_prefix
+    }
+
+    /// Whether a request for `path` falls under this route's path prefix.
+    pub fn matches_path(&self, path: &str) -> bool {
+        match &self.path_prefix {
+            None => true,
+            Some(prefix) => path
+                .strip_prefix(prefix.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['/', '?'])),
+        }
+    }
+
+    /// Where requests are forwarded, e.g. `localhost:3000`, `unix:/path/to.sock`
+    /// or `https://staging.example.com`. Unique per backend.
+    pub fn target(&self) -> String {
+        match (&self.upstream, &self.socket) {
+            (Some(upstream), _) => upstream.to_string(),
+            (None, Some(socket)) => format!("unix:{}", socket.display()),
+            (None, None) => format!("localhost:{}", self.port),
+        }
+    }
+
+    /// `path_and_query` as the backend should see it.
+    pub fn backend_path(&self, path_and_query: &str) -> String {
+        match &self.path_prefix {
+            Some(prefix) if self.strip_prefix && self.matches_path(path_and_query) => {
+                let rest = &path_and_query[prefix.len()..];
+                if rest.starts_with('/') {
+                    rest.to_string()
+                } else {
+                    format!("/{}", rest)
+                }
+            }
+            _ => path_and_query.to_string(),
+        }
+    }
+}
+
+/// A backend that is not on this machine's loopback interface, written as
+/// `host:port`, `http://host[:port]` or `https://host[:port]`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Upstream {
+    /// Host name or IP address, without brackets for IPv6.
+    pub host: String,
+    pub port: u16,
+    /// Connect over TLS, verifying the certificate for `host`.
+    pub tls: bool,
+}
+
+impl Upstream {
+    /// `host[:port]` as sent in the Host header; the port is left out when it
+    /// is the scheme's default.
+    pub fn authority(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        let default_port = if self.tls { 443 } else { 80 };
+        if self.port == default_port {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.tls { "https" } else { "http" }
+    }
+}
+
+impl std::fmt::Display for Upstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}", self.scheme(), self.authority())
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = anyhow::Error;
+
+    fn from_str(input: &str) -> Result<Self> {
+        let (tls, rest) = match input.split_once("://") {
+            Some(("http", rest)) => (false, rest),
+            Some(("https", rest)) => (true, rest),
+            Some((scheme, _)) => {
+                return Err(anyhow!("Unsupported upstream scheme '{}' (use http or https)", scheme));
+            }
+            // A bare `host:port` needs the port, or it could be a typo'd name
+            None if !input.contains(':') => {
+                return Err(anyhow!("Upstream '{}' needs a port, e.g. {}:8080", input, input));
+            }
+            None => (false, input),
+        };
+        let authority = rest.strip_suffix('/').unwrap_or(rest);
+        if authority.contains(['/', '?', '#', '@']) {
+            return Err(anyhow!(
+                "Upstream '{}' must be a bare origin, without path, query or credentials",
+                input
+            ));
+        }
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, "")) => (host, None),
+                Some((host, port)) => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+                None => return Err(anyhow!("Unclosed '[' in upstream '{}'", input)),
+            },
+            None => match authority.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (authority, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(anyhow!("Upstream '{}' has no host", input));
+        }
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(|| anyhow!("Invalid port in upstream '{}'", input))?,
+            None if tls => 443,
+            None => 80,
+        };
+        Ok(Upstream {
+            host: host.to_ascii_lowercase(),
+            port,
+            tls,
+        })
+    }
+}
+
+impl TryFrom<String> for Upstream {
+    type Error = anyhow::Error;
+
+    fn try_from(input: String) -> Result<Self> {
+        input.parse()
+    }
+}
+
+impl From<Upstream> for String {
+    fn from(upstream: Upstream) -> Self {
+        upstream.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HealthCheck {
+    /// The backend accepts TCP connections, which a hung app still does.
+    Tcp,
+    /// `GET <path>` answers with `status`, or with any 2xx/3xx status if unset.
+    Http {
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        status: Option<u16>,
+    },
+}
+
+impl FromStr for HealthCheck {
+    type Err = anyhow::Error;
+
+    /// Parse `tcp`, `http:/path` or `http:/path=STATUS`.
+    fn from_str(input: &str) -> Result<Self> {
+        if input == "tcp" {
+            return Ok(HealthCheck::Tcp);
+        }
+        let Some(target) = input.strip_prefix("http:") else {
+            return Err(anyhow!(
+                "Unknown health check '{}' (expected tcp, http:/path or http:/path=STATUS)",
+                input
+            ));
+        };
+        let (path, status) = match target.rsplit_once('=') {
+            Some((path, status)) if status.len() == 3 && status.parse::<u16>().is_ok() => {
+                (path, status.parse().ok())
+            }
+            _ => (target, None),
+        };
+        if !path.starts_with('/') {
+            return Err(anyhow!("Health check path must start with '/': {}", path));
+        }
+        Ok(HealthCheck::Http {
+            path: path.to_string(),
+            status,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteState {
+    /// The app was launched but has not passed its readiness probe yet.
+    Starting,
+    #[default]
+    Ready,
+    /// The app did not become ready in time.
+    Unhealthy,
+}
+
+impl RouteState {
+    pub fn is_ready(&self) -> bool {
+        *self == RouteState::Ready
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteState::Starting => "starting",
+            RouteState::Ready => "ready",
+            RouteState::Unhealthy => "unhealthy",
+        }
+    }
+}
+
+/// One request/response pair recorded by the proxy's request inspector.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CapturedExchange {
+    pub id: u64,
+    pub hostname: String,
+    /// Address of the client that sent the request.
+    #[serde(default)]
+    pub client_ip: String,
+    /// Port of the backend the request was forwarded to.
+    #[serde(default)]
+    pub backend_port: u16,
+    /// `http` or `https`, as seen by the proxy.
+    pub scheme: String,
+    pub method: String,
+    /// Path and query as sent by the client.
+    pub uri: String,
+    pub http_version: String,
+    /// Request start in milliseconds since the Unix epoch.
+    pub started_at_ms: u64,
+    pub request_headers: Vec<(String, String)>,
+    pub request_body: CapturedBody,
+    /// Missing when the backend could not produce a response.
+    pub status: Option<u16>,
+    pub response_headers: Vec<(String, String)>,
+    pub response_body: CapturedBody,
+    /// Time until the response headers arrived.
+    pub duration_ms: f64,
+    pub timings: ExchangeTimings,
+    pub error: Option<String>,
+    /// Id of the exchange this one re-sent, for `portless replay`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_of: Option<u64>,
+}
+
+/// Phases of an exchange in milliseconds, as in HAR `timings`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct ExchangeTimings {
+    /// Opening a new backend connection; `None` when a pooled one was reused.
+    pub connect_ms: Option<f64>,
+    /// Forwarding the request until its body was fully sent.
+    pub send_ms: f64,
+    /// Waiting for the response headers.
+    pub wait_ms: f64,
+    /// Streaming the response body to the client.
+    pub receive_ms: f64,
+}
+
+/// The first bytes of a message body, decoded as lossy UTF-8.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CapturedBody {
+    /// Full body size in bytes, including any part that was not kept.
+    pub size: u64,
+    pub truncated: bool,
+    pub text: String,
 }
 
 #[cfg(test)]
@@ -17,6 +370,7 @@ mod tests {
             hostname: "test.localhost".to_string(),
             port: 4000,
             pid: 12345,
+            ..Default::default()
         };
 
         assert_eq!(route.hostname, "test.localhost");
@@ -30,6 +384,7 @@ mod tests {
             hostname: "app.localhost".to_string(),
             port: 4500,
             pid: 99999,
+            ..Default::default()
         };
 
         let json = serde_json::to_string(&route).unwrap();
@@ -48,12 +403,172 @@ mod tests {
         assert_eq!(route.pid, 54321);
     }
 
+    #[test]
+    fn test_route_h2c_flag() {
+        let route = Route {
+            hostname: "grpc.localhost".to_string(),
+            port: 4300,
+            pid: 1,
+            ..Default::default()
+        };
+        // Omitted when false so routes.json stays compatible with older versions
+        assert!(!serde_json::to_string(&route).unwrap().contains("h2c"));
+
+        let json = r#"{"hostname":"grpc.localhost","port":4300,"pid":1,"h2c":true}"#;
+        let route: Route = serde_json::from_str(json).unwrap();
+        assert!(route.h2c);
+    }
+
+    #[test]
+    fn test_route_records_how_to_restart() {
+        let route = Route {
+            hostname: "web.localhost".to_string(),
+            port: 4300,
+            pid: 1,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&route).unwrap();
+        assert!(!json.contains("args") && !json.contains("cwd"));
+
+        let route = Route {
+            args: vec!["--h2c".into(), "web".into(), "npm".into(), "start".into()],
+            cwd: Some(PathBuf::from("/repo/web")),
+            ..route
+        };
+        let json = serde_json::to_string(&route).unwrap();
+        let parsed: Route = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.args, route.args);
+        assert_eq!(parsed.cwd, route.cwd);
+    }
+
+    #[test]
+    fn test_path_prefix() {
+        let mut route = Route {
+            hostname: "app.localhost".to_string(),
+            path_prefix: Some("/api".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(route.name(), "app.localhost/api");
+        assert!(route.matches_path("/api"));
+        assert!(route.matches_path("/api/users"));
+        assert!(route.matches_path("/api?page=2"));
+        assert!(!route.matches_path("/apis"));
+        assert!(!route.matches_path("/"));
+
+        assert_eq!(route.backend_path("/api/users?page=2"), "/api/users?page=2");
+        route.strip_prefix = true;
+        assert_eq!(route.backend_path("/api/users?page=2"), "/users?page=2");
+        assert_eq!(route.backend_path("/api"), "/");
+        assert_eq!(route.backend_path("/api?page=2"), "/?page=2");
+    }
+
+    #[test]
+    fn test_parse_upstream() {
+        let parse = |s: &str| s.parse::<Upstream>().unwrap();
+        assert_eq!(
+            parse("https://Staging.Example.com/"),
+            Upstream {
+                host: "staging.example.com".to_string(),
+                port: 443,
+                tls: true,
+            }
+        );
+        assert_eq!(parse("10.0.0.5:8080").to_string(), "http://10.0.0.5:8080");
+        assert_eq!(parse("http://vm.internal").port, 80);
+        assert_eq!(parse("https://api.example.com:8443").authority(), "api.example.com:8443");
+        let ipv6 = parse("http://[::1]:9000");
+        assert_eq!((ipv6.host.as_str(), ipv6.port), ("::1", 9000));
+        assert_eq!(ipv6.authority(), "[::1]:9000");
+
+        assert!("staging.example.com".parse::<Upstream>().is_err());
+        assert!("ftp://example.com".parse::<Upstream>().is_err());
+        assert!("https://example.com/api".parse::<Upstream>().is_err());
+        assert!("https://:443".parse::<Upstream>().is_err());
+        assert!("example.com:0".parse::<Upstream>().is_err());
+
+        // Stored as the URL string
+        let route = Route {
+            hostname: "staging-api.localhost".to_string(),
+            port: 443,
+            alias: true,
+            upstream: Some(parse("https://staging.example.com")),
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&route).unwrap();
+        assert!(json.contains("\"upstream\":\"https://staging.example.com\""));
+        assert_eq!(serde_json::from_str::<Route>(&json).unwrap(), route);
+        assert_eq!(route.target(), "https://staging.example.com");
+    }
+
+    #[test]
+    fn test_socket_target() {
+        let route = Route {
+            hostname: "py.localhost".to_string(),
+            pid: 1,
+            socket: Some(PathBuf::from("/tmp/portless/sockets/1.sock")),
+            ..Default::default()
+        };
+        assert_eq!(route.target(), "unix:/tmp/portless/sockets/1.sock");
+        let json = serde_json::to_string(&route).unwrap();
+        assert_eq!(serde_json::from_str::<Route>(&json).unwrap(), route);
+        assert!(!serde_json::to_string(&Route::default()).unwrap().contains("socket"));
+    }
+
+    #[test]
+    fn test_route_state() {
+        let route = Route {
+            hostname: "web.localhost".to_string(),
+            port: 4400,
+            pid: 1,
+            ..Default::default()
+        };
+        // Ready routes serialize like those from older versions
+        assert!(!serde_json::to_string(&route).unwrap().contains("state"));
+
+        let starting = Route {
+            state: RouteState::Starting,
+            ..route
+        };
+        let json = serde_json::to_string(&starting).unwrap();
+        assert!(json.contains("\"state\":\"starting\""));
+        assert_eq!(serde_json::from_str::<Route>(&json).unwrap(), starting);
+    }
+
+    #[test]
+    fn test_parse_health_check() {
+        assert_eq!("tcp".parse::<HealthCheck>().unwrap(), HealthCheck::Tcp);
+        assert_eq!(
+            "http:/healthz".parse::<HealthCheck>().unwrap(),
+            HealthCheck::Http {
+                path: "/healthz".to_string(),
+                status: None
+            }
+        );
+        assert_eq!(
+            "http:/status?deep=1=204".parse::<HealthCheck>().unwrap(),
+            HealthCheck::Http {
+                path: "/status?deep=1".to_string(),
+                status: Some(204)
+            }
+        );
+        assert_eq!(
+            "http:/status?deep=1".parse::<HealthCheck>().unwrap(),
+            HealthCheck::Http {
+                path: "/status?deep=1".to_string(),
+                status: None
+            }
+        );
+        assert!("http:healthz".parse::<HealthCheck>().is_err());
+        assert!("udp".parse::<HealthCheck>().is_err());
+    }
+
     #[test]
     fn test_route_clone() {
         let route1 = Route {
             hostname: "clone.localhost".to_string(),
             port: 4100,
             pid: 11111,
+            ..Default::default()
         };
 
         let route2 = route1.clone();
@@ -66,12 +581,14 @@ mod tests {
             hostname: "test.localhost".to_string(),
             port: 4000,
             pid: 12345,
+            ..Default::default()
         };
 
         let route2 = Route {
             hostname: "test.localhost".to_string(),
             port: 4000,
             pid: 12345,
+            ..Default::default()
         };
 
         assert_eq!(route1, route2);
@@ -83,12 +600,14 @@ mod tests {
             hostname: "test1.localhost".to_string(),
             port: 4000,
             pid: 12345,
+            ..Default::default()
         };
 
         let route2 = Route {
             hostname: "test2.localhost".to_string(),
             port: 4000,
             pid: 12345,
+            ..Default::default()
         };
 
         assert_ne!(route1, route2);
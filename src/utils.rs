@@ -1,14 +1,18 @@
 use anyhow::{anyhow, Result};
 use rand::Rng;
 use std::net::TcpListener;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 
+use crate::types::Route;
+
 pub const DEFAULT_PROXY_PORT: u16 = 1355;
 pub const PRIVILEGED_PORT_THRESHOLD: u16 = 1024;
 pub const MIN_APP_PORT: u16 = 4000;
 pub const MAX_APP_PORT: u16 = 4999;
 pub const RANDOM_PORT_ATTEMPTS: usize = 50;
+/// Hostname the proxy answers itself with the dashboard; apps cannot claim it.
+pub const DASHBOARD_HOSTNAME: &str = "portless.localhost";
 
 /// TCP connect timeout when checking if the proxy is listening.
 const SOCKET_TIMEOUT_MS: u64 = 500;
@@ -89,15 +93,37 @@ pub fn get_default_port() -> u16 {
         .unwrap_or(DEFAULT_PROXY_PORT)
 }
 
-/// Format a .localhost URL; omit the port when it is 80 (standard HTTP).
-pub fn format_url(hostname: &str, proxy_port: u16) -> String {
-    if proxy_port == 80 {
-        format!("http://{}", hostname)
+/// Format a .localhost URL; omit the port when it is the scheme's default (80/443).
+pub fn format_url(hostname: &str, proxy_port: u16, https: bool) -> String {
+    let (scheme, default_port) = if https { ("https", 443) } else { ("http", 80) };
+    if proxy_port == default_port {
+        format!("{}://{}", scheme, hostname)
     } else {
-        format!("http://{}:{}", hostname, proxy_port)
+        format!("{}://{}:{}", scheme, hostname, proxy_port)
     }
 }
 
+/// URL of `route`, including its path prefix.
+pub fn format_route_url(route: &Route, proxy_port: u16, https: bool) -> String {
+    format_url(&route.hostname, proxy_port, https) + route.path_prefix.as_deref().unwrap_or("")
+}
+
+/// Marker file written by a proxy started with `--https`.
+pub fn https_marker_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("proxy.https")
+}
+
+/// Whether the proxy owning `state_dir` terminates TLS.
+pub fn is_https_enabled(state_dir: &Path) -> bool {
+    https_marker_path(state_dir).exists()
+}
+
+/// Unix socket the app started by the `portless` process `pid` listens on
+/// when run with `--socket`.
+pub fn socket_path(state_dir: &Path, pid: u32) -> PathBuf {
+    state_dir.join("sockets").join(format!("{}.sock", pid))
+}
+
 pub fn parse_hostname(input: &str) -> Result<String> {
     let s = input.trim();
     // Strip protocol prefix
@@ -119,11 +145,69 @@ pub fn parse_hostname(input: &str) -> Result<String> {
     }
 
     validate_hostname(&hostname)?;
+    if hostname == DASHBOARD_HOSTNAME {
+        return Err(anyhow!(
+            "\"{}\" is reserved for the portless dashboard",
+            DASHBOARD_HOSTNAME
+        ));
+    }
     Ok(hostname)
 }
 
+/// Path prefix of an app name such as `myapp/api`, normalized to `/api`;
+/// `None` when the name has no path.
+pub fn parse_path_prefix(input: &str) -> Result<Option<String>> {
+    let s = input.trim();
+    let s = s
+        .strip_prefix("https://")
+        .or_else(|| s.strip_prefix("http://"))
+        .unwrap_or(s);
+    let Some((_, path)) = s.split_once('/') else {
+        return Ok(None);
+    };
+    let path = path.trim_matches('/');
+    if path.is_empty() {
+        return Ok(None);
+    }
+    let valid = path.split('/').all(|segment| {
+        !segment.is_empty()
+            && segment != "."
+            && segment != ".."
+            && segment
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "-._~@:+".contains(c))
+    });
+    if !valid {
+        return Err(anyhow!(
+            "Invalid path \"/{}\": use segments of letters, digits and - . _ ~ @ : +",
+            path
+        ));
+    }
+    Ok(Some(format!("/{}", path)))
+}
+
+/// The labels `hostname` has in place of the `*` of a wildcard `pattern` such
+/// as `*.myapp.localhost`: `acme` for `acme.myapp.localhost`, `eu.acme` for
+/// `eu.acme.myapp.localhost`. `None` if `pattern` is not a wildcard or does not match.
+pub fn wildcard_label<'a>(pattern: &str, hostname: &'a str) -> Option<&'a str> {
+    let suffix = pattern.strip_prefix('*')?;
+    hostname
+        .strip_suffix(suffix)
+        .filter(|label| !label.is_empty())
+}
+
+/// Whether `hostname` is `pattern` or matches it as a wildcard.
+pub fn hostname_matches(pattern: &str, hostname: &str) -> bool {
+    pattern == hostname || wildcard_label(pattern, hostname).is_some()
+}
+
 fn validate_hostname(hostname: &str) -> Result<()> {
     let label = hostname.strip_suffix(".localhost").unwrap_or(hostname);
+    // A leading `*.` stands for any subdomain; the rest must be a concrete name.
+    if label == "*" {
+        return Err(anyhow!("A wildcard needs a name after it, e.g. *.myapp"));
+    }
+    let label = label.strip_prefix("*.").unwrap_or(label);
 
     if label.is_empty() {
         return Err(anyhow!("Hostname label cannot be empty"));
@@ -221,6 +305,90 @@ pub fn is_proxy_running(port: u16) -> bool {
     response.to_ascii_lowercase().contains("x-portless: 1")
 }
 
+/// Parse a duration such as `90s`, `5m`, `2h` or `1d`. A bare number means seconds.
+pub fn parse_duration(input: &str) -> Result<Duration> {
+    let s = input.trim();
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (digits, unit) = s.split_at(split);
+    let value: u64 = digits
+        .parse()
+        .map_err(|_| anyhow!("Invalid duration \"{}\": expected e.g. 30s, 5m, 2h", input))?;
+    let secs = match unit {
+        "" | "s" => value,
+        "m" => value * 60,
+        "h" => value * 3600,
+        "d" => value * 86_400,
+        "ms" => return Ok(Duration::from_millis(value)),
+        _ => {
+            return Err(anyhow!(
+                "Invalid duration unit \"{}\" in \"{}\": use ms, s, m, h or d",
+                unit,
+                input
+            ))
+        }
+    };
+    Ok(Duration::from_secs(secs))
+}
+
+/// Parse an environment variable given as `KEY=VALUE`.
+pub fn parse_env_var(input: &str) -> Result<(String, String)> {
+    match input.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(anyhow!("Invalid environment variable \"{}\": expected KEY=VALUE", input)),
+    }
+}
+
+fn local_time(unix_ms: u64) -> nix::libc::tm {
+    let secs = (unix_ms / 1000) as nix::libc::time_t;
+    // SAFETY: localtime_r only writes to the zero-initialised struct we own.
+    unsafe {
+        let mut tm: nix::libc::tm = std::mem::zeroed();
+        nix::libc::localtime_r(&secs, &mut tm);
+        tm
+    }
+}
+
+/// Local wall-clock time (`HH:MM:SS.mmm`) for a Unix timestamp in milliseconds.
+pub fn format_clock(unix_ms: u64) -> String {
+    let tm = local_time(unix_ms);
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        unix_ms % 1000
+    )
+}
+
+/// Local time in Common Log Format, e.g. `10/Oct/2000:13:55:36 -0700`.
+pub fn format_clf_time(unix_ms: u64) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let tm = local_time(unix_ms);
+    let offset_min = tm.tm_gmtoff / 60;
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} {}{:02}{:02}",
+        tm.tm_mday,
+        MONTHS[tm.tm_mon.clamp(0, 11) as usize],
+        tm.tm_year + 1900,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        if offset_min < 0 { '-' } else { '+' },
+        offset_min.abs() / 60,
+        offset_min.abs() % 60
+    )
+}
+
+/// UTC timestamp in RFC 3339 format with milliseconds, e.g. `2023-11-14T22:13:20.123Z`.
+pub fn format_rfc3339(unix_ms: u64) -> String {
+    time::OffsetDateTime::from_unix_timestamp_nanos(unix_ms as i128 * 1_000_000)
+        .ok()
+        .and_then(|t| t.format(&time::format_description::well_known::Rfc3339).ok())
+        .unwrap_or_default()
+}
+
 /// Try to find the PID of a process listening on a given TCP port using `lsof`.
 pub fn find_pid_on_port(port: u16) -> Option<u32> {
     let output = std::process::Command::new("lsof")
@@ -261,13 +429,39 @@ mod tests {
 
     #[test]
     fn test_format_url_standard_port() {
-        assert_eq!(format_url("test.localhost", 1355), "http://test.localhost:1355");
-        assert_eq!(format_url("app.localhost", 3000), "http://app.localhost:3000");
+        assert_eq!(format_url("test.localhost", 1355, false), "http://test.localhost:1355");
+        assert_eq!(format_url("app.localhost", 3000, false), "http://app.localhost:3000");
     }
 
     #[test]
     fn test_format_url_port_80() {
-        assert_eq!(format_url("test.localhost", 80), "http://test.localhost");
+        assert_eq!(format_url("test.localhost", 80, false), "http://test.localhost");
+    }
+
+    #[test]
+    fn test_format_route_url() {
+        let route = Route {
+            hostname: "app.localhost".to_string(),
+            path_prefix: Some("/api".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(format_route_url(&route, 1355, false), "http://app.localhost:1355/api");
+        assert_eq!(format_route_url(&route, 443, true), "https://app.localhost/api");
+    }
+
+    #[test]
+    fn test_format_url_https() {
+        assert_eq!(format_url("test.localhost", 1355, true), "https://test.localhost:1355");
+        assert_eq!(format_url("test.localhost", 443, true), "https://test.localhost");
+        assert_eq!(format_url("test.localhost", 80, true), "https://test.localhost:80");
+    }
+
+    #[test]
+    fn test_is_https_enabled() {
+        let temp = tempfile::TempDir::new().unwrap();
+        assert!(!is_https_enabled(temp.path()));
+        std::fs::write(https_marker_path(temp.path()), "").unwrap();
+        assert!(is_https_enabled(temp.path()));
     }
 
     #[test]
@@ -332,6 +526,29 @@ mod tests {
         assert!(parse_hostname("test app").is_err()); // space not allowed
     }
 
+    #[test]
+    fn test_parse_hostname_wildcard() {
+        assert_eq!(parse_hostname("*.myapp").unwrap(), "*.myapp.localhost");
+        assert_eq!(parse_hostname("*.eu.myapp.localhost").unwrap(), "*.eu.myapp.localhost");
+        assert!(parse_hostname("*").is_err());
+        assert!(parse_hostname("*.localhost").is_err());
+        assert!(parse_hostname("*.").is_err());
+        assert!(parse_hostname("my*app").is_err());
+        assert!(parse_hostname("api.*.myapp").is_err());
+    }
+
+    #[test]
+    fn test_wildcard_label() {
+        let pattern = "*.myapp.localhost";
+        assert_eq!(wildcard_label(pattern, "acme.myapp.localhost"), Some("acme"));
+        assert_eq!(wildcard_label(pattern, "eu.acme.myapp.localhost"), Some("eu.acme"));
+        assert_eq!(wildcard_label(pattern, "myapp.localhost"), None);
+        assert_eq!(wildcard_label(pattern, "acmemyapp.localhost"), None);
+        assert_eq!(wildcard_label("myapp.localhost", "myapp.localhost"), None);
+        assert!(hostname_matches("myapp.localhost", "myapp.localhost"));
+        assert!(hostname_matches(pattern, "acme.myapp.localhost"));
+    }
+
     #[test]
     fn test_parse_hostname_invalid_start_end() {
         assert!(parse_hostname("-test").is_err()); // cannot start with hyphen
@@ -339,6 +556,76 @@ mod tests {
         assert!(parse_hostname(".test").is_err()); // cannot start with dot
     }
 
+    #[test]
+    fn test_parse_hostname_reserved() {
+        assert!(parse_hostname("portless").is_err());
+        assert!(parse_hostname("portless.localhost").is_err());
+        assert_eq!(
+            parse_hostname("portless-docs").unwrap(),
+            "portless-docs.localhost"
+        );
+    }
+
+    #[test]
+    fn test_parse_path_prefix() {
+        assert_eq!(parse_path_prefix("myapp").unwrap(), None);
+        assert_eq!(parse_path_prefix("myapp/").unwrap(), None);
+        assert_eq!(parse_path_prefix("myapp/api").unwrap().as_deref(), Some("/api"));
+        assert_eq!(
+            parse_path_prefix("http://myapp.localhost/api/v2/").unwrap().as_deref(),
+            Some("/api/v2")
+        );
+        assert!(parse_path_prefix("myapp/api//v2").is_err());
+        assert!(parse_path_prefix("myapp/../admin").is_err());
+        assert!(parse_path_prefix("myapp/api?x=1").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("45").unwrap(), Duration::from_secs(45));
+        assert_eq!(parse_duration("90s").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("2h").unwrap(), Duration::from_secs(7200));
+        assert_eq!(parse_duration("1d").unwrap(), Duration::from_secs(86_400));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5w").is_err());
+    }
+
+    #[test]
+    fn test_parse_env_var() {
+        assert_eq!(
+            parse_env_var("RUST_LOG=debug,hyper=info").unwrap(),
+            ("RUST_LOG".to_string(), "debug,hyper=info".to_string())
+        );
+        assert_eq!(parse_env_var("EMPTY=").unwrap().1, "");
+        assert!(parse_env_var("RUST_LOG").is_err());
+        assert!(parse_env_var("=debug").is_err());
+    }
+
+    #[test]
+    fn test_format_clock() {
+        let clock = format_clock(1_700_000_000_123);
+        assert_eq!(clock.len(), 12);
+        assert!(clock.ends_with(":20.123"), "{}", clock);
+    }
+
+    #[test]
+    fn test_format_clf_time() {
+        let clf = format_clf_time(1_700_000_000_123);
+        // e.g. "14/Nov/2023:22:13:20 +0000", shifted by the local offset
+        assert_eq!(clf.len(), 26, "{}", clf);
+        assert!(clf.contains("/2023:"), "{}", clf);
+        assert!(clf.contains(":20 "), "{}", clf);
+    }
+
+    #[test]
+    fn test_format_rfc3339() {
+        assert_eq!(format_rfc3339(1_700_000_000_123), "2023-11-14T22:13:20.123Z");
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+    }
+
     #[test]
     fn test_escape_html() {
         assert_eq!(escape_html("hello"), "hello");
@@ -0,0 +1,209 @@
+use notify::{RecursiveMode, Watcher};
+use std::ffi::OsStr;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crate::routes::{RouteStore, ROUTES_FILE};
+use crate::types::Route;
+
+/// Poll interval used when filesystem notifications are unavailable.
+const ROUTE_POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Quiet period after a change notification before reloading (coalesces bursts).
+const RELOAD_DEBOUNCE: Duration = Duration::from_millis(10);
+
+/// Identity of routes.json on disk; any write (atomic rename included) changes it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Fingerprint {
+    modified: SystemTime,
+    len: u64,
+    inode: u64,
+}
+
+/// In-memory copy of routes.json, replaced wholesale whenever the file changes.
+///
+/// Every successful reload bumps `generation`, so consumers can tell whether the
+/// route set they last saw is still current.
+pub struct RouteCache {
+    store: RouteStore,
+    table: RwLock<Arc<Vec<Route>>>,
+    fingerprint: Mutex<Option<Fingerprint>>,
+    generation: AtomicU64,
+}
+
+impl RouteCache {
+    pub fn new(store: RouteStore) -> Self {
+        let cache = Self {
+            store,
+            table: RwLock::new(Arc::new(Vec::new())),
+            fingerprint: Mutex::new(None),
+            generation: AtomicU64::new(0),
+        };
+        cache.refresh();
+        cache
+    }
+
+    pub fn snapshot(&self) -> Arc<Vec<Route>> {
+        self.table
+            .read()
+            .map(|t| t.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    /// Re-read routes.json if it changed since the last load.
+    /// Returns true when the cached table was replaced.
+    pub fn refresh(&self) -> bool {
+        let Ok(mut last) = self.fingerprint.lock() else {
+            return false;
+        };
+        let current = self.current_fingerprint();
+        if *last == current && self.generation() > 0 {
+            return false;
+        }
+        // load_raw for display cache (no PID filtering — fast path)
+        let routes = self.store.load_raw().unwrap_or_default();
+        if let Ok(mut table) = self.table.write() {
+            *table = Arc::new(routes);
+        }
+        *last = current;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        true
+    }
+
+    fn current_fingerprint(&self) -> Option<Fingerprint> {
+        let meta = std::fs::metadata(self.store.routes_path()).ok()?;
+        Some(Fingerprint {
+            modified: meta.modified().ok()?,
+            len: meta.len(),
+            inode: meta.ino(),
+        })
+    }
+}
+
+/// Keep `cache` in sync with routes.json: reload on inotify/FSEvents notifications for
+/// the state dir, or poll the file's metadata if no watcher can be created.
+/// `on_reload` runs after every generation change with the new route set.
+pub fn spawn_route_watcher<F>(cache: Arc<RouteCache>, state_dir: PathBuf, on_reload: F)
+where
+    F: Fn(&[Route]) + Send + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+        if let Ok(event) = res
+            && event
+                .paths
+                .iter()
+                .any(|p| p.file_name() == Some(OsStr::new(ROUTES_FILE)))
+        {
+            let _ = tx.send(());
+        }
+    })
+    .and_then(|mut w| {
+        w.watch(&state_dir, RecursiveMode::NonRecursive)?;
+        Ok(w)
+    });
+
+    let watcher = match watcher {
+        Ok(w) => Some(w),
+        Err(e) => {
+            eprintln!(
+                "portless proxy: file watching unavailable ({}), polling routes every {}ms",
+                e,
+                ROUTE_POLL_INTERVAL.as_millis()
+            );
+            None
+        }
+    };
+
+    tokio::spawn(async move {
+        // Moving the watcher in keeps it alive for as long as the task runs.
+        let polling = watcher.is_none();
+        let _watcher = watcher;
+        let mut seen = cache.generation();
+
+        loop {
+            if polling {
+                tokio::time::sleep(ROUTE_POLL_INTERVAL).await;
+            } else {
+                if rx.recv().await.is_none() {
+                    return;
+                }
+                tokio::time::sleep(RELOAD_DEBOUNCE).await;
+                while rx.try_recv().is_ok() {}
+            }
+
+            cache.refresh();
+            // Also covers reloads triggered elsewhere (e.g. a lookup miss).
+            let generation = cache.generation();
+            if generation != seen {
+                seen = generation;
+                on_reload(&cache.snapshot());
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn route(hostname: &str, port: u16) -> Route {
+        Route {
+            hostname: hostname.to_string(),
+            port,
+            pid: std::process::id(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_refresh_only_reloads_on_change() {
+        let temp = TempDir::new().unwrap();
+        let store = RouteStore::new(temp.path().to_path_buf()).unwrap();
+        let cache = RouteCache::new(RouteStore::new(temp.path().to_path_buf()).unwrap());
+        assert_eq!(cache.generation(), 1);
+        assert!(cache.snapshot().is_empty());
+
+        assert!(!cache.refresh());
+        assert_eq!(cache.generation(), 1);
+
+        store.save(&[route("app.localhost", 4000)]).unwrap();
+        assert!(cache.refresh());
+        assert_eq!(cache.generation(), 2);
+        assert_eq!(cache.snapshot()[0].hostname, "app.localhost");
+
+        assert!(!cache.refresh());
+        assert_eq!(cache.generation(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_watcher_picks_up_changes() {
+        let temp = TempDir::new().unwrap();
+        let store = RouteStore::new(temp.path().to_path_buf()).unwrap();
+        let cache = Arc::new(RouteCache::new(
+            RouteStore::new(temp.path().to_path_buf()).unwrap(),
+        ));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        spawn_route_watcher(cache.clone(), temp.path().to_path_buf(), move |routes| {
+            let _ = tx.send(routes.len());
+        });
+
+        store.save(&[route("a.localhost", 4000), route("b.localhost", 4001)]).unwrap();
+        let count = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(cache.snapshot().len(), 2);
+    }
+}
@@ -16,6 +16,7 @@ fn test_route_store_basic_operations() {
         hostname: "test.localhost".to_string(),
         port: 4000,
         pid: std::process::id(),
+        ..Default::default()
     };
     store.add(route.clone()).unwrap();
 
@@ -25,7 +26,7 @@ fn test_route_store_basic_operations() {
     assert_eq!(routes[0].hostname, "test.localhost");
 
     // Test remove
-    store.remove("test.localhost").unwrap();
+    store.remove("test.localhost", None, None).unwrap();
     let routes = store.load(false).unwrap();
     assert_eq!(routes.len(), 0);
 }
@@ -58,8 +59,9 @@ fn test_port_utilities() {
 fn test_format_url() {
     use portless::utils::format_url;
 
-    assert_eq!(format_url("test.localhost", 1355), "http://test.localhost:1355");
-    assert_eq!(format_url("test.localhost", 80), "http://test.localhost");
+    assert_eq!(format_url("test.localhost", 1355, false), "http://test.localhost:1355");
+    assert_eq!(format_url("test.localhost", 80, false), "http://test.localhost");
+    assert_eq!(format_url("test.localhost", 443, true), "https://test.localhost");
 }
 
 #[test]
@@ -88,6 +90,7 @@ fn test_route_serialization() {
         hostname: "app.localhost".to_string(),
         port: 4500,
         pid: 12345,
+        ..Default::default()
     };
 
     let json = serde_json::to_string(&route).unwrap();
@@ -122,6 +125,7 @@ fn test_route_replacement() {
         hostname: "test.localhost".to_string(),
         port: 4000,
         pid: std::process::id(),
+        ..Default::default()
     };
     store.add(route1).unwrap();
 
@@ -130,6 +134,7 @@ fn test_route_replacement() {
         hostname: "test.localhost".to_string(),
         port: 4100,
         pid: std::process::id(),
+        ..Default::default()
     };
     store.add(route2).unwrap();
 
@@ -139,6 +144,72 @@ fn test_route_replacement() {
     assert_eq!(routes[0].port, 4100);
 }
 
+#[test]
+fn test_path_routes() {
+    use portless::routes::{find_route, RouteStore};
+    use portless::types::Route;
+
+    let temp_dir = TempDir::new().unwrap();
+    let store = RouteStore::new(temp_dir.path().to_path_buf()).unwrap();
+    let mount = |prefix: Option<&str>, port| Route {
+        hostname: "myapp.localhost".to_string(),
+        port,
+        pid: std::process::id(),
+        path_prefix: prefix.map(str::to_string),
+        ..Default::default()
+    };
+    store.add(mount(None, 4000)).unwrap();
+    store.add(mount(Some("/api"), 4100)).unwrap();
+    store.add(mount(Some("/api/admin"), 4200)).unwrap();
+    // Re-registering a path replaces only that path
+    store.add(mount(Some("/api"), 4101)).unwrap();
+
+    let routes = store.load(false).unwrap();
+    assert_eq!(routes.len(), 3);
+    let port = |path| find_route(&routes, "myapp.localhost", path).map(|r| r.port);
+    assert_eq!(port("/"), Some(4000));
+    assert_eq!(port("/apis"), Some(4000));
+    assert_eq!(port("/api"), Some(4101));
+    assert_eq!(port("/api/users"), Some(4101));
+    assert_eq!(port("/api/admin/users"), Some(4200));
+    assert_eq!(find_route(&routes, "other.localhost", "/api"), None);
+
+    store.remove("myapp.localhost", None, None).unwrap();
+    let routes = store.load(false).unwrap();
+    assert_eq!(routes.len(), 2);
+    assert_eq!(find_route(&routes, "myapp.localhost", "/"), None);
+    assert_eq!(find_route(&routes, "myapp.localhost", "/api/x").map(|r| r.port), Some(4101));
+}
+
+#[test]
+fn test_wildcard_routes() {
+    use portless::routes::find_route;
+    use portless::types::Route;
+
+    let route = |hostname: &str, prefix: Option<&str>, port| Route {
+        hostname: hostname.to_string(),
+        port,
+        pid: std::process::id(),
+        path_prefix: prefix.map(str::to_string),
+        ..Default::default()
+    };
+    let routes = vec![
+        route("*.myapp.localhost", None, 4000),
+        route("*.eu.myapp.localhost", None, 4100),
+        route("admin.myapp.localhost", Some("/api"), 4200),
+        route("myapp.localhost", None, 4300),
+    ];
+    let port = |host, path| find_route(&routes, host, path).map(|r| r.port);
+    assert_eq!(port("acme.myapp.localhost", "/"), Some(4000));
+    assert_eq!(port("shop.acme.myapp.localhost", "/"), Some(4000));
+    assert_eq!(port("acme.eu.myapp.localhost", "/"), Some(4100));
+    assert_eq!(port("myapp.localhost", "/"), Some(4300));
+    // Exact routes win, but only for the paths they serve
+    assert_eq!(port("admin.myapp.localhost", "/api/users"), Some(4200));
+    assert_eq!(port("admin.myapp.localhost", "/"), Some(4000));
+    assert_eq!(port("other.localhost", "/"), None);
+}
+
 #[test]
 fn test_concurrent_route_operations() {
     use portless::types::Route;
@@ -157,6 +228,7 @@ fn test_concurrent_route_operations() {
                 hostname: format!("app{}.localhost", i),
                 port: 4000 + i as u16,
                 pid: std::process::id(),
+                ..Default::default()
             };
             store_clone.add(route).unwrap();
         });
@@ -187,11 +259,13 @@ fn test_dead_pid_filtering() {
             hostname: "alive.localhost".to_string(),
             port: 4000,
             pid: std::process::id(), // Current process
+            ..Default::default()
         },
         Route {
             hostname: "dead.localhost".to_string(),
             port: 4001,
             pid: 999999, // Non-existent PID
+            ..Default::default()
         },
     ];
 